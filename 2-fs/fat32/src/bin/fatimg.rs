@@ -0,0 +1,244 @@
+//! Inspects and modifies the FAT file system in a disk image.
+//!
+//! Usage: fatimg <image> <command> [<args>]
+//!
+//! Paths inside the image are absolute. `cp` takes paths inside the image
+//! prefixed with `::`, like mtools. `build` creates `<image>`, formats it
+//! with a single FAT32 partition and copies a host directory into it.
+
+extern crate fat32;
+
+use std::env;
+use std::fmt::Display;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+use std::time::UNIX_EPOCH;
+
+use fat32::traits::{Dir as DirTrait, Entry as EntryTrait, File as FileTrait};
+use fat32::traits::{FileSystem, Metadata as MetadataTrait};
+use fat32::vfat::{self, Entry, Metadata, Shared, Timestamp, VFat};
+
+const USAGE: &str = "usage: fatimg <image> <command> [<args>]
+
+commands:
+    ls [-l] [<path>]           list a directory
+    tree [<path>]              list a directory recursively
+    cat <path>                 print a file
+    cp <from> <to>             copy a file; prefix paths in the image with ::
+    mkdir [-p] <path>          create a directory
+    rm [-r] <path>             remove a file or directory
+    stat <path>                show the metadata of an entry
+    build <size-MiB> <dir>     create the image from a host directory";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+/// Prints `error` prefixed with `context` and exits.
+fn fail<E: Display>(context: &str, error: E) -> ! {
+    eprintln!("fatimg: {}: {}", context, error);
+    process::exit(1);
+}
+
+fn mount(image: &str, write: bool) -> Shared<VFat> {
+    let file = match OpenOptions::new().read(true).write(write).open(image) {
+        Ok(file) => file,
+        Err(e) => fail(image, e)
+    };
+
+    match VFat::from(file) {
+        Ok(vfat) => {
+            vfat.borrow_mut().set_clock(Timestamp::now);
+            vfat
+        }
+        Err(e) => fail(image, format!("not a FAT file system: {:?}", e))
+    }
+}
+
+/// Returns a string of flags for whether `entry` is a directory and for its
+/// attributes, in the style of `ls -l`.
+fn mode(entry: &Entry) -> String {
+    let metadata = entry.metadata();
+    let flags = [
+        (entry.is_dir(), 'd'),
+        (metadata.read_only(), 'r'),
+        (metadata.hidden(), 'h'),
+        (metadata.system(), 's'),
+        (metadata.archive(), 'a'),
+    ];
+    flags.iter().map(|&(set, c)| if set { c } else { '-' }).collect()
+}
+
+fn is_dot(entry: &Entry) -> bool {
+    entry.name() == "." || entry.name() == ".."
+}
+
+fn ls(vfat: &Shared<VFat>, path: &str, long: bool) -> io::Result<()> {
+    for entry in vfat.open_dir(path)?.entries()? {
+        match long {
+            true => println!("{} {:>10} {} {}", mode(&entry), entry.metadata().size(),
+                             entry.metadata().modified(), entry.name()),
+            false => println!("{}", entry.name())
+        }
+    }
+    Ok(())
+}
+
+fn tree(vfat: &Shared<VFat>, path: &Path, prefix: &str) -> io::Result<()> {
+    let entries: Vec<Entry> = vfat.open_dir(path)?.entries()?
+        .filter(|entry| !is_dot(entry))
+        .collect();
+    for (i, entry) in entries.iter().enumerate() {
+        let last = i + 1 == entries.len();
+        println!("{}{}{}", prefix, if last { "`-- " } else { "|-- " }, entry.name());
+        if entry.is_dir() {
+            let prefix = format!("{}{}", prefix, if last { "    " } else { "|   " });
+            tree(vfat, &path.join(entry.name()), &prefix)?;
+        }
+    }
+    Ok(())
+}
+
+fn cat(vfat: &Shared<VFat>, path: &str) -> io::Result<()> {
+    let mut file = vfat.open_file(path)?;
+    io::copy(&mut file, &mut io::stdout())?;
+    Ok(())
+}
+
+/// Copies the host file at `from` to `to` in the image, replacing the file at
+/// `to` if there is one. If `to` is a directory, the file is copied into it.
+fn copy_in(vfat: &Shared<VFat>, from: &Path, to: &Path) -> io::Result<()> {
+    match vfat.open(to) {
+        Ok(ref entry) if entry.is_dir() => {
+            let name = from.file_name().ok_or(
+                io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+            return copy_in(vfat, from, &to.join(name));
+        }
+        Ok(_) => vfat.remove(to, false)?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+        Err(e) => return Err(e)
+    }
+
+    let mut host = fs::File::open(from)?;
+    let mut file = vfat.create_file(to)?;
+    io::copy(&mut host, &mut file)?;
+    let modified = host.metadata()?.modified()?.duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|since| Timestamp::from_unix(since.as_secs()));
+    if let Some(modified) = modified {
+        file.metadata.set_modified(modified);
+    }
+    file.sync()
+}
+
+/// Copies the file at `from` in the image to the host path `to`. If `to` is a
+/// directory, the file is copied into it.
+fn copy_out(vfat: &Shared<VFat>, from: &Path, to: &Path) -> io::Result<()> {
+    let mut file = vfat.open_file(from)?;
+    let to = match to.is_dir() {
+        true => to.join(file.metadata.name.clone()),
+        false => to.to_path_buf()
+    };
+    let mut host = fs::File::create(to)?;
+    io::copy(&mut file, &mut host)?;
+    host.flush()
+}
+
+/// Recursively copies the contents of the host directory `from` into the
+/// directory `to` in the image.
+fn copy_dir_in(vfat: &Shared<VFat>, from: &Path, to: &Path) -> io::Result<()> {
+    for host_entry in fs::read_dir(from)? {
+        let host_entry = host_entry?;
+        let dest = to.join(host_entry.file_name());
+        if host_entry.file_type()?.is_dir() {
+            vfat.create_dir(&dest, false)?;
+            copy_dir_in(vfat, &host_entry.path(), &dest)?;
+        } else {
+            copy_in(vfat, &host_entry.path(), &dest)?;
+        }
+    }
+    Ok(())
+}
+
+fn stat(vfat: &Shared<VFat>, path: &str) -> io::Result<()> {
+    let entry = vfat.open(path)?;
+    let metadata: &Metadata = entry.metadata();
+    println!("  Name: {}", entry.name());
+    println!("  Type: {}", if entry.is_dir() { "directory" } else { "file" });
+    println!("  Size: {}", metadata.size());
+    println!("  Mode: {} ({:#04x})", mode(&entry), metadata.attributes.0);
+    println!("Create: {}", metadata.created());
+    println!("Modify: {}", metadata.modified());
+    println!("Access: {}", metadata.accessed());
+    Ok(())
+}
+
+fn build(image: &str, size_mib: &str, dir: &str) -> io::Result<()> {
+    let size = match size_mib.parse::<u64>() {
+        Ok(size) => size << 20,
+        Err(e) => fail(size_mib, e)
+    };
+
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(true)
+        .open(image)?;
+    file.set_len(size)?;
+    vfat::format(file, size)?;
+
+    let vfat = mount(image, true);
+    copy_dir_in(&vfat, Path::new(dir), Path::new("/"))?;
+    vfat.borrow_mut().sync()?;
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 {
+        usage();
+    }
+    let (image, command) = (&args[0], args[1].as_str());
+    let args = &args[2..];
+    let in_image = |arg: &String| arg.starts_with("::");
+    if command == "build" {
+        if args.len() != 2 {
+            usage();
+        }
+        if let Err(e) = build(image, &args[0], &args[1]) {
+            fail(image, e);
+        }
+        return;
+    }
+
+    let writes = match command {
+        "cp" => args.len() == 2 && in_image(&args[1]),
+        "mkdir" | "rm" => true,
+        _ => false
+    };
+    let vfat = &mount(image, writes);
+    let result = match (command, args.len()) {
+        ("ls", 0) => ls(vfat, "/", false),
+        ("ls", 1) if args[0] == "-l" => ls(vfat, "/", true),
+        ("ls", 1) => ls(vfat, &args[0], false),
+        ("ls", 2) if args[0] == "-l" => ls(vfat, &args[1], true),
+        ("tree", 0) => tree(vfat, Path::new("/"), ""),
+        ("tree", 1) => tree(vfat, Path::new(&args[0]), ""),
+        ("cat", 1) => cat(vfat, &args[0]),
+        ("cp", 2) if in_image(&args[0]) && !in_image(&args[1]) =>
+            copy_out(vfat, Path::new(&args[0][2..]), Path::new(&args[1])),
+        ("cp", 2) if !in_image(&args[0]) && in_image(&args[1]) =>
+            copy_in(vfat, Path::new(&args[0]), Path::new(&args[1][2..])),
+        ("mkdir", 1) => vfat.create_dir(&args[0], false).map(|_| ()),
+        ("mkdir", 2) if args[0] == "-p" => vfat.create_dir(&args[1], true).map(|_| ()),
+        ("rm", 1) => vfat.remove(&args[0], false),
+        ("rm", 2) if args[0] == "-r" => vfat.remove(&args[1], true),
+        ("stat", 1) => stat(vfat, &args[0]),
+        _ => usage()
+    };
+
+    if let Err(e) = result.and_then(|_| vfat.borrow_mut().sync()) {
+        fail(command, e);
+    }
+}
+
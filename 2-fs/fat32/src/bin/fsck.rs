@@ -0,0 +1,73 @@
+//! Checks, and optionally repairs, a FAT disk image.
+//!
+//! Usage: fsck [--repair] <image>
+//!
+//! Exits with 0 if the file system is consistent, 1 if problems were found and
+//! repaired, 4 if problems were found and left unrepaired, and 8 if the image
+//! couldn't be checked at all.
+
+extern crate fat32;
+
+use std::env;
+use std::fs::OpenOptions;
+use std::process;
+
+use fat32::vfat::VFat;
+
+fn usage() -> ! {
+    eprintln!("usage: fsck [--repair] <image>");
+    process::exit(8);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (repair, path) = match args.len() {
+        1 => (false, &args[0]),
+        2 if args[0] == "--repair" || args[0] == "-r" => (true, &args[1]),
+        _ => usage()
+    };
+
+    let file = match OpenOptions::new().read(true).write(repair).open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("fsck: {}: {}", path, e);
+            process::exit(8);
+        }
+    };
+
+    let vfat = match VFat::from(file) {
+        Ok(vfat) => vfat,
+        Err(e) => {
+            eprintln!("fsck: {}: not a FAT file system: {:?}", path, e);
+            process::exit(8);
+        }
+    };
+
+    let result = match repair {
+        true => vfat.borrow_mut().repair(),
+        false => vfat.borrow_mut().check()
+    };
+    let problems = match result {
+        Ok(problems) => problems,
+        Err(e) => {
+            eprintln!("fsck: {}: {}", path, e);
+            process::exit(8);
+        }
+    };
+
+    for problem in &problems {
+        println!("{}", problem);
+    }
+
+    match (problems.is_empty(), repair) {
+        (true, _) => println!("{}: clean", path),
+        (false, true) => {
+            println!("{}: {} problems repaired", path, problems.len());
+            process::exit(1);
+        }
+        (false, false) => {
+            println!("{}: {} problems found", path, problems.len());
+            process::exit(4);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+use std::mem;
+
+use traits::BlockDevice;
+use vfat::Error;
+
+/// The exFAT main boot sector.
+#[repr(C, packed)]
+pub struct BootSector {
+    pub jmp: [u8; 3],
+    /// "EXFAT   "
+    pub file_system_name: [u8; 8],
+    /// overlaps the FAT BPB; must be zero
+    pub __zero: [u8; 53],
+    pub partition_offset: u64,
+    /// size of the volume in sectors
+    pub volume_length: u64,
+    /// sector of the first FAT, relative to the boot sector
+    pub fat_offset: u32,
+    /// size of a FAT in sectors
+    pub fat_length: u32,
+    /// sector of the first cluster, relative to the boot sector
+    pub cluster_heap_offset: u32,
+    pub cluster_count: u32,
+    pub root_cluster: u32,
+    pub volume_serial: u32,
+    pub revision: u16,
+    pub volume_flags: u16,
+    /// log2 of the number of bytes per sector
+    pub bytes_per_sector_shift: u8,
+    /// log2 of the number of sectors per cluster
+    pub sectors_per_cluster_shift: u8,
+    pub num_fats: u8,
+    pub drive_select: u8,
+    pub percent_in_use: u8,
+    pub __reserved: [u8; 7],
+    pub boot_code: [u8; 390],
+    pub bootable_signature: u16
+}
+
+impl BootSector {
+    /// Reads the exFAT boot sector from sector `sector` of device `device`.
+    ///
+    /// # Errors
+    ///
+    /// If the boot signature or file system name is invalid, returns an error
+    /// of `BadSignature`.
+    pub fn from<T: BlockDevice>(mut device: T, sector: u64) -> Result<BootSector, Error> {
+        let mut raw_boot_sector = [0u8; 512];
+        device.read_sector(sector, &mut raw_boot_sector)?;
+
+        let block: BootSector = unsafe { mem::transmute(raw_boot_sector) };
+        if block.bootable_signature != 0xAA55 || &block.file_system_name != b"EXFAT   " {
+            return Err(Error::BadSignature);
+        }
+        Ok(block)
+    }
+
+    /// Whether the geometry fields are within the ranges allowed by the exFAT
+    /// specification.
+    pub fn is_valid(&self) -> bool {
+        self.bytes_per_sector_shift >= 9 && self.bytes_per_sector_shift <= 12
+            && self.bytes_per_sector_shift as u32 + self.sectors_per_cluster_shift as u32 <= 25
+            && (self.num_fats == 1 || self.num_fats == 2)
+            && self.fat_offset != 0 && self.fat_length != 0
+            && self.cluster_count != 0
+            && self.root_cluster >= 2
+            && self.root_cluster - 2 < self.cluster_count
+    }
+
+    /// The number of bytes in a sector.
+    pub fn bytes_per_sector(&self) -> u64 {
+        1 << self.bytes_per_sector_shift
+    }
+
+    /// The number of sectors in a cluster.
+    pub fn sectors_per_cluster(&self) -> u64 {
+        1 << self.sectors_per_cluster_shift
+    }
+}
+
+impl fmt::Debug for BootSector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BootSector")
+            .field("volume_length", &{ self.volume_length })
+            .field("fat_offset", &{ self.fat_offset })
+            .field("fat_length", &{ self.fat_length })
+            .field("cluster_heap_offset", &{ self.cluster_heap_offset })
+            .field("cluster_count", &{ self.cluster_count })
+            .field("root_cluster", &{ self.root_cluster })
+            .field("volume_serial", &{ self.volume_serial })
+            .field("bytes_per_sector", &self.bytes_per_sector())
+            .field("sectors_per_cluster", &self.sectors_per_cluster())
+            .field("num_fats", &self.num_fats)
+            .finish()
+    }
+}
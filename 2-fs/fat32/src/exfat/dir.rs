@@ -0,0 +1,185 @@
+use std::ffi::OsStr;
+use std::io;
+use std::vec::IntoIter;
+
+use traits;
+use util::from_le;
+use vfat::{Shared, Cluster};
+use exfat::{Chain, Entry, ExFat, File, Metadata, Timestamp};
+
+pub(crate) const BYTES_IN_ENTRY: usize = 32;
+
+pub(crate) const ENTRY_END: u8 = 0x00;
+pub(crate) const ENTRY_BITMAP: u8 = 0x81;
+pub(crate) const ENTRY_UPCASE: u8 = 0x82;
+pub(crate) const ENTRY_LABEL: u8 = 0x83;
+pub(crate) const ENTRY_FILE: u8 = 0x85;
+pub(crate) const ENTRY_STREAM: u8 = 0xC0;
+pub(crate) const ENTRY_NAME: u8 = 0xC1;
+
+/// A stream extension flag: the data's clusters are consecutive and are not
+/// recorded in the FAT.
+const NO_FAT_CHAIN: u8 = 0x02;
+
+#[derive(Debug)]
+pub struct Dir {
+    pub metadata: Metadata,
+    pub(crate) chain: Chain,
+    pub exfat: Shared<ExFat>,
+}
+
+pub struct DirIter {
+    entries: IntoIter<Entry>,
+}
+
+impl Iterator for DirIter {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        self.entries.next()
+    }
+}
+
+impl Dir {
+    /// Finds the entry named `name` in `self` and returns it. Names are
+    /// compared case-insensitively using the volume's up-case table.
+    ///
+    /// # Errors
+    ///
+    /// If no entry with name `name` exists in `self`, an error of `NotFound` is
+    /// returned.
+    ///
+    /// If `name` contains invalid UTF-8 characters, an error of `InvalidInput`
+    /// is returned.
+    pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry> {
+        let name = name.as_ref().to_str()
+            .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "name not valid utf8"))?;
+        let name = upcase_name(&self.exfat.borrow(), name);
+
+        let entries = traits::Dir::entries(self)?;
+        let exfat = self.exfat.borrow();
+        for entry in entries {
+            if upcase_name(&exfat, traits::Entry::name(&entry)) == name {
+                return Ok(entry);
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, "entry not found"))
+    }
+}
+
+impl traits::Dir for Dir {
+    type Entry = Entry;
+    type Iter = DirIter;
+
+    /// Returns an iterator over the file entry sets in `self`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if an entry set is truncated or has a
+    /// bad checksum.
+    fn entries(&self) -> io::Result<DirIter> {
+        let mut buf = Vec::new();
+        self.exfat.borrow_mut().read_chain(self.chain, &mut buf)?;
+        let raw: Vec<&[u8]> = buf.chunks(BYTES_IN_ENTRY).collect();
+
+        let mut entries = Vec::new();
+        let mut i = 0;
+        while i < raw.len() {
+            match raw[i][0] {
+                ENTRY_END => break,
+                ENTRY_FILE => {
+                    let end = i + 1 + raw[i][1] as usize;
+                    if end > raw.len() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                  "truncated entry set"));
+                    }
+                    entries.push(self.parse_entry_set(&raw[i..end])?);
+                    i = end;
+                }
+                // deleted entries and critical or benign entries we don't use
+                _ => i += 1
+            }
+        }
+        Ok(DirIter { entries: entries.into_iter() })
+    }
+}
+
+impl Dir {
+    /// Decodes the file, stream extension and file name entries in `set`.
+    fn parse_entry_set(&self, set: &[&[u8]]) -> io::Result<Entry> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if set.len() < 3 || set[1][0] != ENTRY_STREAM {
+            return Err(invalid("entry set has no stream extension"));
+        }
+        if entry_set_checksum(set) != (set[0][2] as u16 | (set[0][3] as u16) << 8) {
+            return Err(invalid("bad entry set checksum"));
+        }
+
+        let (file, stream) = (set[0], set[1]);
+        let name_length = stream[3] as usize;
+        let mut name: Vec<u16> = Vec::new();
+        for entry in set[2..].iter().filter(|entry| entry[0] == ENTRY_NAME) {
+            name.extend(entry[2..].chunks(2).map(|c| c[0] as u16 | (c[1] as u16) << 8));
+        }
+        if name.len() < name_length || name_length == 0 {
+            return Err(invalid("file name is shorter than its length"));
+        }
+        name.truncate(name_length);
+
+        let chain = allocation(stream, stream[1] & NO_FAT_CHAIN != 0);
+        let timestamp = |offset: usize, increment: Option<usize>, utc_offset: usize| Timestamp {
+            value: from_le(&file[offset..offset + 4]),
+            increment_10ms: increment.map(|i| file[i]).unwrap_or(0),
+            utc_offset: file[utc_offset],
+        };
+        let metadata = Metadata {
+            name: String::from_utf16_lossy(&name),
+            size: chain.len,
+            attributes: file[4] as u16 | (file[5] as u16) << 8,
+            created: timestamp(8, Some(20), 22),
+            last_modified: timestamp(12, Some(21), 23),
+            accessed: timestamp(16, None, 24),
+        };
+
+        let exfat = self.exfat.clone();
+        Ok(match metadata.is_dir() {
+            true => Entry::Dir(Dir { metadata, chain, exfat }),
+            false => Entry::File(File::new(metadata, chain, le64(&stream[8..16]), exfat))
+        })
+    }
+}
+
+/// Returns `name` as UTF-16 converted to upper case with the volume's up-case
+/// table.
+fn upcase_name(exfat: &ExFat, name: &str) -> Vec<u16> {
+    name.encode_utf16().map(|c| exfat.upcase(c)).collect()
+}
+
+/// Reads the first cluster and data length fields, which are at the same
+/// offsets in stream extension, allocation bitmap and up-case table entries.
+pub(crate) fn allocation(entry: &[u8], contiguous: bool) -> Chain {
+    Chain {
+        first: Cluster(from_le(&entry[20..24])),
+        len: le64(&entry[24..32]),
+        contiguous,
+    }
+}
+
+fn le64(bytes: &[u8]) -> u64 {
+    from_le(&bytes[..4]) as u64 | (from_le(&bytes[4..8]) as u64) << 32
+}
+
+/// Computes the checksum of an entry set, skipping the checksum field itself
+/// in the first entry.
+pub(crate) fn entry_set_checksum(set: &[&[u8]]) -> u16 {
+    let mut checksum = 0u16;
+    for (i, entry) in set.iter().enumerate() {
+        for (j, &byte) in entry.iter().enumerate() {
+            if i == 0 && (j == 2 || j == 3) {
+                continue;
+            }
+            checksum = checksum.rotate_right(1).wrapping_add(byte as u16);
+        }
+    }
+    checksum
+}
@@ -0,0 +1,53 @@
+use traits;
+use exfat::{File, Dir, Metadata};
+
+#[derive(Debug)]
+pub enum Entry {
+    File(File),
+    Dir(Dir)
+}
+
+impl traits::Entry for Entry {
+    type File = File;
+    type Dir = Dir;
+    type Metadata = Metadata;
+
+    fn name(&self) -> &str {
+        &self.metadata().name
+    }
+
+    fn metadata(&self) -> &Self::Metadata {
+        match self {
+            Entry::Dir(dir) => &dir.metadata,
+            Entry::File(file) => &file.metadata
+        }
+    }
+
+    fn as_file(&self) -> Option<&Self::File> {
+        match self {
+            Entry::File(file) => Some(file),
+            Entry::Dir(_) => None
+        }
+    }
+
+    fn as_dir(&self) -> Option<&Self::Dir> {
+        match self {
+            Entry::Dir(dir) => Some(dir),
+            Entry::File(_) => None
+        }
+    }
+
+    fn into_file(self) -> Option<Self::File> {
+        match self {
+            Entry::File(file) => Some(file),
+            Entry::Dir(_) => None
+        }
+    }
+
+    fn into_dir(self) -> Option<Self::Dir> {
+        match self {
+            Entry::Dir(dir) => Some(dir),
+            Entry::File(_) => None
+        }
+    }
+}
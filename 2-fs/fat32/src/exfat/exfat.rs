@@ -0,0 +1,433 @@
+use std::io;
+use std::path::{Component, Path};
+use std::cmp::min;
+
+use util::from_le;
+use mbr::MasterBootRecord;
+use gpt::GuidPartitionTable;
+use vfat::{Shared, Cluster, CachedDevice, Partition, Error, StatFs};
+use exfat::{BootSector, Dir, Entry, File};
+use exfat::dir::{self, BYTES_IN_ENTRY};
+use traits;
+use traits::{FileSystem, BlockDevice};
+
+/// The MBR partition type used for exFAT (and NTFS) partitions.
+const PARTITION_TYPE_EXFAT: u8 = 0x07;
+
+/// The FAT entry that ends a cluster chain.
+const EOC: u32 = 0xFFFFFFFF;
+
+/// The clusters that hold the data of a file or directory.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Chain {
+    /// The first cluster, or cluster 0 if no clusters are allocated.
+    pub first: Cluster,
+    /// The length of the data in bytes.
+    pub len: u64,
+    /// Whether the clusters are consecutive and not recorded in the FAT.
+    pub contiguous: bool,
+}
+
+#[derive(Debug)]
+pub struct ExFat {
+    device: CachedDevice,
+    bytes_per_sector: u64,
+    sectors_per_cluster: u64,
+    fat_start_sector: u64,
+    cluster_heap_start_sector: u64,
+    cluster_count: u32,
+    volume_serial: u32,
+    root: Chain,
+    bitmap: Option<Chain>,
+    /// The up-case table, indexed by UTF-16 code unit.
+    upcase: Vec<u16>,
+    label: String,
+}
+
+impl ExFat {
+    /// Mounts the exFAT file system on `device`. `device` may be a disk with
+    /// an MBR or a GPT, in which case the first partition holding an exFAT
+    /// file system is mounted, or a "superfloppy" whose first sector is the
+    /// exFAT boot sector.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if no partition holds an exFAT file system and
+    /// `BadSignature` if the file system's metadata is invalid.
+    pub fn from<T>(mut device: T) -> Result<Shared<ExFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        if let Ok(boot_sector) = BootSector::from(&mut device, 0) {
+            if boot_sector.is_valid() {
+                return ExFat::mount(device, 0);
+            }
+        }
+
+        let mbr = MasterBootRecord::from(&mut device)?;
+        let offsets: Vec<u64> = match mbr.is_protective() {
+            true => GuidPartitionTable::from(&mut device)?.partitions().iter()
+                .map(|partition| partition.first_lba)
+                .collect(),
+            false => mbr.partitions().iter()
+                .filter(|partition| partition.partition_type() == PARTITION_TYPE_EXFAT)
+                .map(|partition| partition.relative_sector() as u64)
+                .collect()
+        };
+
+        for offset in offsets {
+            if let Ok(boot_sector) = BootSector::from(&mut device, offset) {
+                if boot_sector.is_valid() {
+                    return ExFat::mount(device, offset);
+                }
+            }
+        }
+        Err(Error::NotFound)
+    }
+
+    /// Mounts the file system whose boot sector is at sector `offset` of
+    /// `device`, loading the allocation bitmap, up-case table and volume
+    /// label entries from the root directory.
+    fn mount<T>(mut device: T, offset: u64) -> Result<Shared<ExFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        let boot_sector = BootSector::from(&mut device, offset)?;
+        if !boot_sector.is_valid() {
+            return Err(Error::BadSignature);
+        } else if boot_sector.bytes_per_sector() % device.sector_size() != 0 {
+            return Err(Error::InvalidBpb("sector smaller than the device's sectors"));
+        }
+
+        let device = CachedDevice::new(
+            device,
+            Partition {
+                start: offset,
+                sector_size: boot_sector.bytes_per_sector()
+            });
+
+        let mut exfat = ExFat {
+            device,
+            bytes_per_sector: boot_sector.bytes_per_sector(),
+            sectors_per_cluster: boot_sector.sectors_per_cluster(),
+            fat_start_sector: offset + boot_sector.fat_offset as u64,
+            cluster_heap_start_sector: offset + boot_sector.cluster_heap_offset as u64,
+            cluster_count: boot_sector.cluster_count,
+            volume_serial: boot_sector.volume_serial,
+            root: Chain { first: Cluster(boot_sector.root_cluster), len: 0, contiguous: false },
+            bitmap: None,
+            upcase: Vec::new(),
+            label: String::new(),
+        };
+
+        // The root directory has no stream extension entry recording its
+        // length, so it is as long as its cluster chain.
+        let mut cluster = exfat.root.first;
+        let mut num_clusters = 1;
+        while let Some(next) = exfat.next_cluster(cluster)? {
+            if num_clusters > exfat.cluster_count {
+                return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidData,
+                                                    "cluster chain has a cycle")));
+            }
+            cluster = next;
+            num_clusters += 1;
+        }
+        exfat.root.len = num_clusters as u64 * exfat.cluster_size() as u64;
+
+        let mut buf = Vec::new();
+        let root = exfat.root;
+        exfat.read_chain(root, &mut buf)?;
+        let mut upcase = None;
+        for raw in buf.chunks(BYTES_IN_ENTRY) {
+            match raw[0] {
+                dir::ENTRY_END => break,
+                dir::ENTRY_BITMAP if exfat.bitmap.is_none() => {
+                    exfat.bitmap = Some(dir::allocation(raw, false));
+                }
+                dir::ENTRY_UPCASE => {
+                    upcase = Some((from_le(&raw[4..8]), dir::allocation(raw, false)));
+                }
+                dir::ENTRY_LABEL => {
+                    let len = min(raw[1] as usize, 11);
+                    let chars: Vec<u16> = raw[2..2 + 2 * len].chunks(2)
+                        .map(|c| c[0] as u16 | (c[1] as u16) << 8)
+                        .collect();
+                    exfat.label = String::from_utf16_lossy(&chars);
+                }
+                _ => {}
+            }
+        }
+
+        if let Some((checksum, chain)) = upcase {
+            let mut table = Vec::new();
+            exfat.read_chain(chain, &mut table)?;
+            if upcase_checksum(&table) != checksum {
+                return Err(Error::BadSignature);
+            }
+            exfat.upcase = decompress_upcase(&table);
+        }
+
+        Ok(Shared::new(exfat))
+    }
+
+    /// The size of a cluster in bytes.
+    pub fn cluster_size(&self) -> usize {
+        (self.bytes_per_sector * self.sectors_per_cluster) as usize
+    }
+
+    /// The volume label, which is empty if the volume has none.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// The volume serial number.
+    pub fn volume_id(&self) -> u32 {
+        self.volume_serial
+    }
+
+    /// Returns the total and free clusters of the volume, counting the clear
+    /// bits in the allocation bitmap.
+    pub fn statfs(&mut self) -> io::Result<StatFs> {
+        let bitmap = self.bitmap.ok_or(io::Error::new(io::ErrorKind::InvalidData,
+                                                      "volume has no allocation bitmap"))?;
+        let mut buf = Vec::new();
+        self.read_chain(bitmap, &mut buf)?;
+
+        let mut used = 0;
+        for cluster in 0..self.cluster_count as usize {
+            match buf.get(cluster / 8) {
+                Some(byte) if byte & (1 << (cluster % 8)) == 0 => {},
+                _ => used += 1
+            }
+        }
+        Ok(StatFs {
+            total_clusters: self.cluster_count,
+            free_clusters: self.cluster_count - used,
+            cluster_size: self.cluster_size() as u32,
+        })
+    }
+
+    /// Returns the upper case form of the UTF-16 code unit `c` according to
+    /// the volume's up-case table.
+    pub(crate) fn upcase(&self, c: u16) -> u16 {
+        match self.upcase.get(c as usize) {
+            Some(&upper) => upper,
+            None if c < 0x80 => (c as u8).to_ascii_uppercase() as u16,
+            None => c
+        }
+    }
+
+    fn cluster_sector(&self, cluster: Cluster) -> u64 {
+        self.cluster_heap_start_sector + (cluster.0 as u64 - 2) * self.sectors_per_cluster
+    }
+
+    /// Returns an `InvalidData` error unless `cluster` is a cluster of the
+    /// cluster heap.
+    fn check_cluster(&self, cluster: Cluster) -> io::Result<()> {
+        if cluster.0 < 2 || cluster.0 - 2 >= self.cluster_count {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid cluster number"));
+        }
+        Ok(())
+    }
+
+    /// Returns the cluster following `cluster` in the FAT, or `None` if
+    /// `cluster` is the last in its chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the FAT entry is free, bad or
+    /// otherwise does not point to a cluster.
+    pub(crate) fn next_cluster(&mut self, cluster: Cluster) -> io::Result<Option<Cluster>> {
+        self.check_cluster(cluster)?;
+        let offset = cluster.0 as u64 * 4;
+        let sector = self.fat_start_sector + offset / self.bytes_per_sector;
+        let index = (offset % self.bytes_per_sector) as usize;
+        let entry = from_le(&self.device.get(sector)?[index..index + 4]);
+        match entry {
+            EOC => Ok(None),
+            next => {
+                self.check_cluster(Cluster(next))?;
+                Ok(Some(Cluster(next)))
+            }
+        }
+    }
+
+    /// Returns the `index`th cluster of `chain`, starting the walk of a FAT
+    /// chain at `from`, the known `(index, cluster)` pair, if given.
+    pub(crate) fn cluster_at(
+        &mut self,
+        chain: Chain,
+        from: Option<(u64, Cluster)>,
+        index: u64
+    ) -> io::Result<Cluster> {
+        let corrupt = || io::Error::new(io::ErrorKind::InvalidData,
+                                        "cluster chain is shorter than the data");
+        if chain.contiguous {
+            let cluster = Cluster(chain.first.0.wrapping_add(index as u32));
+            self.check_cluster(cluster)?;
+            return Ok(cluster);
+        }
+
+        let (mut i, mut cluster) = match from {
+            Some((i, cluster)) if i <= index => (i, cluster),
+            _ => (0, chain.first)
+        };
+        while i < index {
+            cluster = self.next_cluster(cluster)?.ok_or_else(corrupt)?;
+            i += 1;
+        }
+        self.check_cluster(cluster)?;
+        Ok(cluster)
+    }
+
+    /// Reads from an offset of a cluster into a buffer. Reads until `buf` is
+    /// full or the end of the cluster is reached, whichever comes first, and
+    /// returns the number of bytes read.
+    pub(crate) fn read_cluster(
+        &mut self,
+        cluster: Cluster,
+        offset: usize,
+        buf: &mut [u8]
+    ) -> io::Result<usize> {
+        let sector_size = self.bytes_per_sector as usize;
+        let start_sector = self.cluster_sector(cluster);
+        let end = min(self.cluster_size(), offset + buf.len());
+
+        let mut pos = offset;
+        while pos < end {
+            let sector_offset = pos % sector_size;
+            let amount = min(sector_size - sector_offset, end - pos);
+            let data = self.device.get(start_sector + (pos / sector_size) as u64)?;
+            buf[pos - offset..pos - offset + amount]
+                .copy_from_slice(&data[sector_offset..sector_offset + amount]);
+            pos += amount;
+        }
+        Ok(end.saturating_sub(offset))
+    }
+
+    /// Reads all of the data in `chain` into `buf`, returning the number of
+    /// bytes read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the length of `chain` is more than
+    /// the clusters it can span hold.
+    pub(crate) fn read_chain(&mut self, chain: Chain, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let cluster_size = self.cluster_size() as u64;
+        let max_clusters = match chain.contiguous {
+            true => self.cluster_count.saturating_sub(chain.first.0.saturating_sub(2)),
+            false => self.cluster_count
+        };
+        if chain.len > max_clusters as u64 * cluster_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "data length is longer than the volume"));
+        }
+
+        let start = buf.len();
+        buf.resize(start + chain.len as usize, 0);
+
+        let mut current = None;
+        let mut pos = 0;
+        while pos < chain.len {
+            let index = pos / cluster_size;
+            let cluster = self.cluster_at(chain, current, index)?;
+            current = Some((index, cluster));
+            pos += self.read_cluster(cluster, 0, &mut buf[start + pos as usize..])? as u64;
+        }
+        Ok(chain.len as usize)
+    }
+}
+
+/// Computes the checksum of an up-case table as stored on disk.
+fn upcase_checksum(table: &[u8]) -> u32 {
+    table.iter().fold(0u32, |sum, &byte| sum.rotate_right(1).wrapping_add(byte as u32))
+}
+
+/// Expands an up-case table, in which `0xFFFF` followed by a count stands for
+/// that many characters that map to themselves.
+fn decompress_upcase(table: &[u8]) -> Vec<u16> {
+    let mut upcase: Vec<u16> = Vec::new();
+    let mut words = table.chunks(2)
+        .filter(|word| word.len() == 2)
+        .map(|word| word[0] as u16 | (word[1] as u16) << 8);
+    while let Some(word) = words.next() {
+        if upcase.len() > 0xFFFF {
+            break;
+        }
+
+        match word {
+            0xFFFF => {
+                let count = words.next().unwrap_or(0) as usize;
+                for _ in 0..min(count, 0x10000 - upcase.len()) {
+                    let c = upcase.len() as u16;
+                    upcase.push(c);
+                }
+            }
+            upper => upcase.push(upper)
+        }
+    }
+    upcase
+}
+
+/// A read-only file system: creating, renaming and removing entries returns
+/// an error of `PermissionDenied`.
+impl<'a> FileSystem for &'a Shared<ExFat> {
+    type File = File;
+    type Dir = Dir;
+    type Entry = Entry;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<Self::Entry> {
+        let mut traversed = Vec::new();
+        traversed.push(Entry::Dir(Dir {
+            metadata: Default::default(),
+            chain: self.borrow().root,
+            exfat: (*self).clone(),
+        }));
+
+        for component in path.as_ref().components() {
+            match component {
+                Component::Normal(name) => {
+                    match traits::Entry::as_dir(traversed.last().unwrap()) {
+                        Some(dir) => {
+                            traversed.push(dir.find(name)?);
+                        },
+                        None => {
+                            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                                      "tried to traverse through file."));
+                        }
+                    }
+                }
+                Component::CurDir | Component::RootDir => { },
+                Component::ParentDir => {
+                    if traversed.len() > 1 {
+                        traversed.pop();
+                    }
+                },
+                Component::Prefix(_) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              "path prefixes are not supported"));
+                }
+            }
+        }
+        Ok(traversed.pop().unwrap())
+    }
+
+    fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
+        Err(read_only())
+    }
+
+    fn create_dir<P: AsRef<Path>>(self, _path: P, _parents: bool) -> io::Result<Self::Dir> {
+        Err(read_only())
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(self, _from: P, _to: Q) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn remove<P: AsRef<Path>>(self, _path: P, _children: bool) -> io::Result<()> {
+        Err(read_only())
+    }
+}
+
+/// The error returned by operations that would modify the file system.
+pub(crate) fn read_only() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "read only file system")
+}
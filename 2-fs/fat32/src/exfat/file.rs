@@ -0,0 +1,130 @@
+use std::cmp::min;
+use std::io::{self, SeekFrom};
+
+use traits;
+use vfat::{Cluster, Shared};
+use exfat::{Chain, ExFat, Metadata};
+use exfat::exfat::read_only;
+
+#[derive(Debug)]
+pub struct File {
+    pub metadata: Metadata,
+    pub exfat: Shared<ExFat>,
+    pub offset: u64,
+    pub(crate) chain: Chain,
+    /// The number of bytes that have been written. The rest of the file reads
+    /// as zeroes.
+    valid_len: u64,
+    /// The most recently accessed cluster and its index in the file's chain.
+    current: Option<(u64, Cluster)>
+}
+
+impl File {
+    pub(crate) fn new(
+        metadata: Metadata,
+        chain: Chain,
+        valid_len: u64,
+        exfat: Shared<ExFat>
+    ) -> File {
+        File {
+            valid_len: min(valid_len, metadata.size),
+            metadata,
+            exfat,
+            offset: 0,
+            chain,
+            current: None,
+        }
+    }
+}
+
+impl io::Seek for File {
+    /// Seek to offset `pos` in the file.
+    ///
+    /// # Errors
+    ///
+    /// Seeking before the start of a file or beyond the end of the file results
+    /// in an `InvalidInput` error.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_offset: i64 = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => self.metadata.size as i64 + offset,
+            SeekFrom::Current(offset) => self.offset as i64 + offset
+        };
+
+        if new_offset < 0 || new_offset as u64 > self.metadata.size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek is invalid"));
+        }
+
+        self.offset = new_offset as u64;
+        Ok(self.offset)
+    }
+}
+
+impl traits::File for File {
+    /// Does nothing: exFAT files are read only.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.metadata.size
+    }
+
+    /// Always fails with an error of `PermissionDenied`.
+    fn set_len(&mut self, _size: u64) -> io::Result<()> {
+        Err(read_only())
+    }
+}
+
+impl io::Write for File {
+    /// Always fails with an error of `PermissionDenied`.
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(read_only())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Read for File {
+    /// Reads from the current offset into `buf`, one cluster at a time.
+    /// Contiguous files are read without consulting the FAT.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the file's cluster chain is shorter
+    /// than its size or is otherwise corrupt.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let end = min(self.metadata.size, self.offset + buf.len() as u64);
+        if self.offset >= end {
+            return Ok(0);
+        }
+
+        let shared = self.exfat.clone();
+        let mut exfat = shared.borrow_mut();
+        let cluster_size = exfat.cluster_size() as u64;
+
+        let amount = (end - self.offset) as usize;
+        let data_end = min(end, self.valid_len).saturating_sub(self.offset) as usize;
+        let mut bytes_read = 0;
+        while bytes_read < data_end {
+            let offset = self.offset + bytes_read as u64;
+            let index = offset / cluster_size;
+            let cluster = exfat.cluster_at(self.chain, self.current, index)?;
+            self.current = Some((index, cluster));
+            bytes_read += exfat.read_cluster(
+                cluster,
+                (offset % cluster_size) as usize,
+                &mut buf[bytes_read..data_end])?;
+        }
+
+        // past the valid data length, files read as zeroes
+        for byte in buf[data_end..amount].iter_mut() {
+            *byte = 0;
+        }
+
+        self.offset = end;
+        Ok(amount)
+    }
+}
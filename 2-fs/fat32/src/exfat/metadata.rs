@@ -0,0 +1,110 @@
+use traits;
+
+/// A timestamp as represented in exFAT directory entries: a FAT date and time
+/// with an additional 10 ms increment and an optional UTC offset.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timestamp {
+    /// The date in the upper 16 bits and the time in the lower 16 bits.
+    pub(crate) value: u32,
+    /// Hundredths of a second to add to the 2-second precision time.
+    pub(crate) increment_10ms: u8,
+    /// The offset from UTC in 15 minute intervals in the lower 7 bits, valid
+    /// only if the top bit is set.
+    pub(crate) utc_offset: u8,
+}
+
+impl Timestamp {
+    /// Hundredths of a second past `second()`. Always in range [0, 100).
+    pub fn centisecond(&self) -> u8 {
+        self.increment_10ms % 100
+    }
+
+    /// The offset of the timestamp from UTC in minutes, if it is recorded.
+    pub fn utc_offset_minutes(&self) -> Option<i16> {
+        match self.utc_offset & 0x80 {
+            0 => None,
+            // sign-extend the 7-bit two's complement value
+            _ => Some((((self.utc_offset << 1) as i8 >> 1) as i16) * 15)
+        }
+    }
+}
+
+impl traits::Timestamp for Timestamp {
+    fn year(&self) -> usize {
+        1980 + (self.value >> 25) as usize
+    }
+
+    fn month(&self) -> u8 {
+        ((self.value >> 21) & 0xF) as u8
+    }
+
+    fn day(&self) -> u8 {
+        ((self.value >> 16) & 0b11111) as u8
+    }
+
+    fn hour(&self) -> u8 {
+        ((self.value >> 11) & 0b11111) as u8
+    }
+
+    fn minute(&self) -> u8 {
+        ((self.value >> 5) & 0b111111) as u8
+    }
+
+    fn second(&self) -> u8 {
+        2 * (self.value & 0b11111) as u8 + self.increment_10ms / 100
+    }
+}
+
+/// Metadata for an exFAT directory entry set.
+#[derive(Default, Debug, Clone)]
+pub struct Metadata {
+    pub name: String,
+    pub size: u64,
+    pub attributes: u16,
+    pub created: Timestamp,
+    pub accessed: Timestamp,
+    pub last_modified: Timestamp,
+}
+
+impl Metadata {
+    /// Whether the entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.attributes & 0x10 != 0
+    }
+}
+
+impl traits::Metadata for Metadata {
+    type Timestamp = Timestamp;
+
+    fn read_only(&self) -> bool {
+        self.attributes & 0x01 != 0
+    }
+
+    fn hidden(&self) -> bool {
+        self.attributes & 0x02 != 0
+    }
+
+    fn system(&self) -> bool {
+        self.attributes & 0x04 != 0
+    }
+
+    fn archive(&self) -> bool {
+        self.attributes & 0x20 != 0
+    }
+
+    fn created(&self) -> Self::Timestamp {
+        self.created
+    }
+
+    fn accessed(&self) -> Self::Timestamp {
+        self.accessed
+    }
+
+    fn modified(&self) -> Self::Timestamp {
+        self.last_modified
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
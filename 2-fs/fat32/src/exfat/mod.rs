@@ -0,0 +1,14 @@
+pub(crate) mod boot;
+pub(crate) mod exfat;
+pub(crate) mod dir;
+pub(crate) mod file;
+pub(crate) mod entry;
+pub(crate) mod metadata;
+
+pub use self::boot::BootSector;
+pub use self::exfat::ExFat;
+pub use self::dir::{Dir, DirIter};
+pub use self::file::File;
+pub use self::entry::Entry;
+pub use self::metadata::{Metadata, Timestamp};
+pub(crate) use self::exfat::Chain;
@@ -0,0 +1,284 @@
+use std::{fmt, io, mem};
+
+use mbr::{self, MasterBootRecord};
+use traits::BlockDevice;
+use util;
+
+/// The partition type of the single partition in a protective MBR.
+const PROTECTIVE_PARTITION_TYPE: u8 = 0xEE;
+
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+const MIN_HEADER_SIZE: usize = 92;
+const MIN_ENTRY_SIZE: usize = 128;
+
+/// The largest partition entry array that will be read, in bytes.
+const MAX_ENTRIES_SIZE: usize = 1 << 20;
+
+/// A GUID as stored on disk, with its first three fields little endian.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// The type of unused partition entries.
+    pub const UNUSED: Guid = Guid([0; 16]);
+
+    /// EBD0A0A2-B9E5-4433-87C0-68B6B72699C7, the type of FAT, exFAT and NTFS
+    /// partitions.
+    pub const MICROSOFT_BASIC_DATA: Guid = Guid([
+        0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44,
+        0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7
+    ]);
+
+    /// C12A7328-F81F-11D2-BA4B-00A0C93EC93B, the type of EFI system
+    /// partitions, which are FAT formatted.
+    pub const EFI_SYSTEM: Guid = Guid([
+        0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11,
+        0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B
+    ]);
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(f, "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+               b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9])?;
+        for byte in &b[10..] {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Guid({})", self)
+    }
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct GptHeader {
+    pub signature: [u8; 8],
+    pub revision: u32,
+    pub header_size: u32,
+    /// CRC32 of the first `header_size` bytes with this field zeroed
+    pub header_crc32: u32,
+    pub __reserved: u32,
+    /// LBA of this header
+    pub my_lba: u64,
+    /// LBA of the other copy of the header
+    pub alternate_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: Guid,
+    pub partition_entry_lba: u64,
+    pub num_partition_entries: u32,
+    pub size_of_partition_entry: u32,
+    pub partition_entry_array_crc32: u32,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct GptPartitionEntry {
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    pub first_lba: u64,
+    /// inclusive
+    pub last_lba: u64,
+    pub attributes: u64,
+    name: [u16; 36],
+}
+
+/// The GUID partition table (GPT).
+#[derive(Debug)]
+pub struct GuidPartitionTable {
+    header: GptHeader,
+    partitions: Vec<GptPartitionEntry>,
+    from_backup: bool,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// There was an I/O error while reading the GPT.
+    Io(io::Error),
+    /// There was an error reading the protective MBR.
+    Mbr(mbr::Error),
+    /// The MBR is not a protective MBR, so the disk does not use a GPT.
+    NotProtective,
+    /// Neither the primary nor the backup GPT header and partition entries
+    /// have valid signatures and checksums.
+    BadHeader,
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<mbr::Error> for Error {
+    fn from(error: mbr::Error) -> Error {
+        Error::Mbr(error)
+    }
+}
+
+impl GuidPartitionTable {
+    /// Reads and returns the GUID partition table from `device`, which must
+    /// begin with a protective MBR.
+    ///
+    /// The primary header at LBA 1 is used if it and the partition entries it
+    /// describes have valid checksums. Otherwise the backup header at the last
+    /// LBA of the disk, found through the protective MBR, is used.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotProtective` if the MBR has no protective partition and
+    /// `BadHeader` if neither header is valid. Returns `Mbr(err)` or `Io(err)`
+    /// if the error `err` occured while reading the MBR or GPT.
+    pub fn from<T: BlockDevice>(mut device: T) -> Result<GuidPartitionTable, Error> {
+        let mbr = MasterBootRecord::from(&mut device)?;
+        let (start, num_sectors) = mbr.find_partition(PROTECTIVE_PARTITION_TYPE)
+            .ok_or(Error::NotProtective)?;
+
+        if let Some(gpt) = GuidPartitionTable::read(&mut device, 1, false)? {
+            return Ok(gpt);
+        }
+
+        // The protective partition spans the whole disk, or as much of it as
+        // fits in 32 bits.
+        let last_lba = (start as u64 + num_sectors as u64).checked_sub(1)
+            .ok_or(Error::BadHeader)?;
+        match GuidPartitionTable::read(&mut device, last_lba, true)? {
+            Some(gpt) => Ok(gpt),
+            None => Err(Error::BadHeader)
+        }
+    }
+
+    /// Reads the header at `lba` and its partition entries. Returns `None` if
+    /// either is invalid.
+    fn read<T: BlockDevice>(
+        device: &mut T,
+        lba: u64,
+        from_backup: bool
+    ) -> io::Result<Option<GuidPartitionTable>> {
+        let mut sector = Vec::new();
+        device.read_all_sector(lba, &mut sector)?;
+
+        let header = match GuidPartitionTable::parse_header(&sector, lba) {
+            Some(header) => header,
+            None => return Ok(None)
+        };
+
+        let entry_size = header.size_of_partition_entry as usize;
+        let entries_size = header.num_partition_entries as usize * entry_size;
+        let mut entries = Vec::new();
+        let mut entry_lba = header.partition_entry_lba;
+        while entries.len() < entries_size {
+            device.read_all_sector(entry_lba, &mut entries)?;
+            entry_lba += 1;
+        }
+        entries.truncate(entries_size);
+
+        if util::crc32(&entries) != header.partition_entry_array_crc32 {
+            return Ok(None);
+        }
+
+        let partitions = entries.chunks(entry_size).map(|chunk| {
+            let mut raw_entry = [0u8; MIN_ENTRY_SIZE];
+            raw_entry.copy_from_slice(&chunk[..MIN_ENTRY_SIZE]);
+            unsafe { mem::transmute::<_, GptPartitionEntry>(raw_entry) }
+        }).filter(|entry| entry.type_guid != Guid::UNUSED).collect();
+
+        Ok(Some(GuidPartitionTable { header, partitions, from_backup }))
+    }
+
+    /// Parses and validates the header in `sector`, which was read from LBA
+    /// `lba`.
+    fn parse_header(sector: &[u8], lba: u64) -> Option<GptHeader> {
+        if sector.len() < MIN_HEADER_SIZE || &sector[..8] != SIGNATURE {
+            return None;
+        }
+
+        let mut raw_header = [0u8; MIN_HEADER_SIZE];
+        raw_header.copy_from_slice(&sector[..MIN_HEADER_SIZE]);
+        let header: GptHeader = unsafe { mem::transmute(raw_header) };
+
+        let header_size = header.header_size as usize;
+        if header_size < MIN_HEADER_SIZE || header_size > sector.len() || header.my_lba != lba {
+            return None;
+        }
+
+        let mut checked = sector[..header_size].to_vec();
+        checked[16..20].copy_from_slice(&[0; 4]);
+        if util::crc32(&checked) != header.header_crc32 {
+            return None;
+        }
+
+        let entry_size = header.size_of_partition_entry as usize;
+        let entries_size = header.num_partition_entries as u64 * entry_size as u64;
+        if entry_size < MIN_ENTRY_SIZE || entry_size % 8 != 0
+            || entries_size > MAX_ENTRIES_SIZE as u64 {
+            return None;
+        }
+        Some(header)
+    }
+
+    /// The used partition entries, in the order they appear in the table.
+    pub fn partitions(&self) -> &[GptPartitionEntry] {
+        &self.partitions
+    }
+
+    /// The GUID of the disk.
+    pub fn disk_guid(&self) -> Guid {
+        self.header.disk_guid
+    }
+
+    /// Whether the table was read from the backup header because the primary
+    /// header or its partition entries were corrupt.
+    pub fn from_backup(&self) -> bool {
+        self.from_backup
+    }
+
+    /// Return the offset in sectors of the first Microsoft basic data or EFI
+    /// system partition if one exists.
+    pub fn get_fat_partition_offset(&self) -> Option<u64> {
+        self.partitions.iter()
+            .find(|p| p.type_guid == Guid::MICROSOFT_BASIC_DATA || p.type_guid == Guid::EFI_SYSTEM)
+            .map(|p| p.first_lba)
+    }
+}
+
+impl GptPartitionEntry {
+    /// The name of the partition.
+    pub fn name(&self) -> String {
+        let name = self.name;
+        let end = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        ::std::char::decode_utf16(name[..end].iter().cloned())
+            .map(|r| r.unwrap_or('\u{FFFD}'))
+            .collect()
+    }
+}
+
+impl fmt::Debug for GptHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GptHeader")
+            .field("my_lba", &{ self.my_lba })
+            .field("alternate_lba", &{ self.alternate_lba })
+            .field("disk_guid", &{ self.disk_guid })
+            .field("partition_entry_lba", &{ self.partition_entry_lba })
+            .field("num_partition_entries", &{ self.num_partition_entries })
+            .finish()
+    }
+}
+
+impl fmt::Debug for GptPartitionEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GptPartitionEntry")
+            .field("type_guid", &{ self.type_guid })
+            .field("first_lba", &{ self.first_lba })
+            .field("last_lba", &{ self.last_lba })
+            .field("name", &self.name())
+            .finish()
+    }
+}
@@ -14,7 +14,10 @@ mod tests;
 mod mbr;
 mod util;
 
+pub mod gpt;
+
 pub mod vfat;
+pub mod exfat;
 pub mod traits;
 
 pub use mbr::*;
@@ -1,22 +1,25 @@
-use std::{fmt, io};
-use util;
+use std::{cmp, fmt, io, mem};
 
 use traits::BlockDevice;
 
-const TABLE_OFFSET: usize = 446;
-const ENTRY_SIZE: usize = 16;
-
+/// A cylinder-head-sector address, as stored in a partition table entry.
 #[repr(C, packed)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub struct CHS {
-    // FIXME: Fill me in.
+    head: u8,
+    /// bits 0-5 are the sector, bits 6-7 are the high bits of the cylinder
+    sector_cylinder_hi: u8,
+    cylinder_lo: u8,
 }
 
 #[repr(C, packed)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub struct PartitionEntry {
-    bootable: bool,
+    /// 0x80 if the partition is bootable, 0 otherwise
+    boot_indicator: u8,
+    start: CHS,
     partition_type: u8,
+    end: CHS,
     /// offset, in sectors, from start of disk to start of partition
     relative_sector: u32,
     total_sectors: u32,
@@ -25,7 +28,10 @@ pub struct PartitionEntry {
 /// The master boot record (MBR).
 #[repr(C, packed)]
 pub struct MasterBootRecord {
+    bootstrap: [u8; 436],
+    disk_id: [u8; 10],
     partitions: [PartitionEntry; 4],
+    signature: [u8; 2],
 }
 
 #[derive(Debug)]
@@ -36,9 +42,29 @@ pub enum Error {
     UnknownBootIndicator(u8),
     /// The MBR magic signature was invalid.
     BadSignature,
+    /// Partitions `.0` and `.1` (0-indexed) overlap.
+    Overlap(u8, u8),
+    /// Partition `.0` (0-indexed) is empty, starts at sector 0 or extends past
+    /// the end of the device.
+    OutOfBounds(u8),
 }
 
+/// The number of heads and sectors per track assumed when converting LBAs to
+/// CHS addresses.
+const HEADS: u64 = 255;
+const SECTORS_PER_TRACK: u64 = 63;
+
 impl MasterBootRecord {
+    /// Returns an MBR with an empty partition table and no bootstrap code.
+    pub fn new() -> MasterBootRecord {
+        MasterBootRecord {
+            bootstrap: [0; 436],
+            disk_id: [0; 10],
+            partitions: [PartitionEntry::empty(); 4],
+            signature: [0x55, 0xAA],
+        }
+    }
+
     /// Reads and returns the master boot record (MBR) from `device`.
     ///
     /// # Errors
@@ -52,68 +78,326 @@ impl MasterBootRecord {
         if let Err(io_err) = device.read_sector(0, &mut raw_mbr) {
             return Err(Error::Io(io_err));
         }
-        
-        if raw_mbr[510] != 0x55 || raw_mbr[511] != 0xAA {
+
+        let mbr: MasterBootRecord = unsafe { mem::transmute(raw_mbr) };
+        if mbr.signature != [0x55, 0xAA] {
             return Err(Error::BadSignature);
         }
 
-        let mut partitions = [
-            PartitionEntry::new(),
-            PartitionEntry::new(),
-            PartitionEntry::new(),
-            PartitionEntry::new(),
-        ];
-
-        for i in 0..partitions.len() {
-            let start = TABLE_OFFSET + i*ENTRY_SIZE;
-            let raw_entry = &raw_mbr[start..start + ENTRY_SIZE];
-            if let Err(_) = partitions[i].read_data(raw_entry) {
+        for (i, partition) in mbr.partitions.iter().enumerate() {
+            if partition.boot_indicator != 0 && partition.boot_indicator != 0x80 {
                 return Err(Error::UnknownBootIndicator(i as u8));
             }
         }
 
-        Ok(MasterBootRecord { partitions })
+        Ok(mbr)
+    }
+
+    /// The four entries of the partition table, including unused ones.
+    pub fn partitions(&self) -> &[PartitionEntry; 4] {
+        &self.partitions
+    }
+
+    /// Replaces partition table entry `index` with `entry`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OutOfBounds(index)` if `entry` is used but empty or starts at
+    /// sector 0, and `Overlap` if it overlaps another used entry. The table is
+    /// unchanged on error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not between 0 and 3.
+    pub fn set_partition(&mut self, index: usize, entry: PartitionEntry) -> Result<(), Error> {
+        let old = mem::replace(&mut self.partitions[index], entry);
+        if let Err(e) = self.check_partition(index) {
+            self.partitions[index] = old;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Marks partition table entry `index` as unused.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not between 0 and 3.
+    pub fn remove_partition(&mut self, index: usize) {
+        self.partitions[index] = PartitionEntry::empty();
+    }
+
+    /// Changes the size of partition `index` to `total_sectors`, keeping its
+    /// start, and updates its end CHS address.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error as `set_partition()` does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not between 0 and 3.
+    pub fn resize_partition(&mut self, index: usize, total_sectors: u32) -> Result<(), Error> {
+        let mut entry = self.partitions[index];
+        let start = entry.relative_sector;
+        entry.set_lba(start, total_sectors);
+        self.set_partition(index, entry)
+    }
+
+    /// Checks that every used partition is non-empty, lies within the first
+    /// `device_sectors` sectors of the device after sector 0, and doesn't
+    /// overlap any other.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OutOfBounds(n)` or `Overlap(n, m)` for the first invalid
+    /// partition `n`.
+    pub fn validate(&self, device_sectors: u64) -> Result<(), Error> {
+        for (i, partition) in self.partitions.iter().enumerate() {
+            self.check_partition(i)?;
+            match partition.last_sector() {
+                Some(last) if partition.is_used() && last >= device_sectors => {
+                    return Err(Error::OutOfBounds(i as u8));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that partition `index` is valid on its own and doesn't overlap
+    /// any other used partition.
+    fn check_partition(&self, index: usize) -> Result<(), Error> {
+        let partition = &self.partitions[index];
+        if !partition.is_used() {
+            return Ok(());
+        }
+
+        let last = match partition.last_sector() {
+            Some(last) if partition.relative_sector != 0 => last,
+            _ => return Err(Error::OutOfBounds(index as u8))
+        };
+
+        for (i, other) in self.partitions.iter().enumerate() {
+            if i == index || !other.is_used() {
+                continue;
+            }
+            if let Some(other_last) = other.last_sector() {
+                if partition.relative_sector as u64 <= other_last
+                    && other.relative_sector as u64 <= last {
+                    let (a, b) = (cmp::min(i, index), cmp::max(i, index));
+                    return Err(Error::Overlap(a as u8, b as u8));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates the partition table against a device with `device_sectors`
+    /// sectors and writes the MBR, including its 0x55AA signature, to sector 0
+    /// of `device`. Any bytes of sector 0 past the first 512 are preserved.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error as `validate()` does, or `Io(err)` if the I/O error
+    /// `err` occured while writing the MBR.
+    pub fn write<T: BlockDevice>(&self, mut device: T, device_sectors: u64) -> Result<(), Error> {
+        self.validate(device_sectors)?;
+
+        let mut sector = Vec::new();
+        device.read_all_sector(0, &mut sector).map_err(Error::Io)?;
+
+        let raw_mbr: [u8; 512] = unsafe { mem::transmute_copy(self) };
+        sector[..512].copy_from_slice(&raw_mbr);
+        sector[510..512].copy_from_slice(&[0x55, 0xAA]);
+        device.write_sector(0, &sector).map_err(Error::Io)?;
+        Ok(())
+    }
+
+    /// The optional disk signature and the two bytes that follow it.
+    pub fn disk_id(&self) -> [u8; 10] {
+        self.disk_id
+    }
+
+    /// Returns the offset and size in sectors of the first partition of type
+    /// `partition_type`, if one exists.
+    pub(crate) fn find_partition(&self, partition_type: u8) -> Option<(u32, u32)> {
+        self.partitions.iter()
+            .find(|partition| partition.partition_type == partition_type)
+            .map(|partition| (partition.relative_sector, partition.total_sectors))
+    }
+
+    /// Whether this is the protective MBR of a disk partitioned with a GUID
+    /// partition table.
+    pub fn is_protective(&self) -> bool {
+        self.find_partition(0xEE).is_some()
     }
 
     /// Return the offset in physical sectors of the first FAT partition if one
     /// exists
     pub fn get_fat_partition_offset(&self) -> Option<u32> {
-        for partition in self.partitions.iter() {
-            if partition.partition_type == 0x0b || partition.partition_type == 0x0c {
-                return Some(partition.relative_sector);
-            }
-        }
-        None
+        self.partitions.iter()
+            .find(|partition| partition.is_fat())
+            .map(|partition| partition.relative_sector)
     }
 }
 
 impl fmt::Debug for MasterBootRecord {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("MasterBootRecord")
+            .field("disk_id", &self.disk_id)
             .field("partitions", &self.partitions)
             .finish()
     }
 }
 
 impl PartitionEntry {
-    pub fn new() -> PartitionEntry {
+    /// Returns a non-bootable partition entry of type `partition_type` that
+    /// starts at sector `relative_sector` and is `total_sectors` sectors long.
+    /// Its CHS addresses are computed from its LBAs.
+    pub fn new(partition_type: u8, relative_sector: u32, total_sectors: u32) -> PartitionEntry {
+        let mut entry = PartitionEntry::empty();
+        entry.partition_type = partition_type;
+        entry.set_lba(relative_sector, total_sectors);
+        entry
+    }
+
+    /// Returns an unused partition entry.
+    pub fn empty() -> PartitionEntry {
         PartitionEntry {
-            bootable: false,
+            boot_indicator: 0,
+            start: CHS::new(0, 0, 0),
             partition_type: 0,
+            end: CHS::new(0, 0, 0),
             relative_sector: 0,
-            total_sectors: 0
+            total_sectors: 0,
         }
     }
 
-    pub fn read_data(&mut self, buf: &[u8]) -> Result<(), ()> {
-        self.bootable = match buf[0] {
-            0 => false,
-            0x80 => true,
-            _ => return Err(())
-        };
-        self.partition_type = buf[4];
-        self.relative_sector = util::from_le(&buf[8..12]);
-        self.total_sectors = util::from_le(&buf[12..16]);
-        Ok(())
+    pub fn set_bootable(&mut self, bootable: bool) {
+        self.boot_indicator = if bootable { 0x80 } else { 0 };
+    }
+
+    pub fn set_partition_type(&mut self, partition_type: u8) {
+        self.partition_type = partition_type;
+    }
+
+    /// Sets the first sector and size of the partition, and sets its CHS
+    /// addresses to match.
+    pub fn set_lba(&mut self, relative_sector: u32, total_sectors: u32) {
+        self.relative_sector = relative_sector;
+        self.total_sectors = total_sectors;
+        self.start = CHS::from_lba(relative_sector as u64);
+        self.end = CHS::from_lba(self.last_sector().unwrap_or(relative_sector as u64));
+    }
+
+    pub fn set_start_chs(&mut self, start: CHS) {
+        self.start = start;
+    }
+
+    pub fn set_end_chs(&mut self, end: CHS) {
+        self.end = end;
+    }
+
+    /// Whether the partition is marked as bootable (active).
+    pub fn bootable(&self) -> bool {
+        self.boot_indicator == 0x80
+    }
+
+    /// The partition type, e.g. 0x0C for FAT32 with LBA addressing.
+    pub fn partition_type(&self) -> u8 {
+        self.partition_type
+    }
+
+    /// Whether the entry describes a partition. Unused entries have type 0.
+    pub fn is_used(&self) -> bool {
+        self.partition_type != 0
+    }
+
+    /// Whether the partition type is one of the FAT types: 0x01 (FAT12),
+    /// 0x04, 0x06 and 0x0E (FAT16) or 0x0B and 0x0C (FAT32).
+    pub fn is_fat(&self) -> bool {
+        match self.partition_type {
+            0x01 | 0x04 | 0x06 | 0x0E | 0x0B | 0x0C => true,
+            _ => false
+        }
+    }
+
+    /// The CHS address of the first sector of the partition.
+    pub fn start_chs(&self) -> CHS {
+        self.start
+    }
+
+    /// The CHS address of the last sector of the partition.
+    pub fn end_chs(&self) -> CHS {
+        self.end
+    }
+
+    /// The LBA of the first sector of the partition.
+    pub fn relative_sector(&self) -> u32 {
+        self.relative_sector
+    }
+
+    /// The number of sectors in the partition.
+    pub fn total_sectors(&self) -> u32 {
+        self.total_sectors
+    }
+
+    /// The LBA of the last sector of the partition, or `None` if the partition
+    /// is empty.
+    pub fn last_sector(&self) -> Option<u64> {
+        match self.total_sectors {
+            0 => None,
+            n => Some(self.relative_sector as u64 + n as u64 - 1)
+        }
+    }
+}
+
+impl CHS {
+    /// Returns the CHS address of `cylinder`, `head` and `sector`. Only the low
+    /// 10 bits of `cylinder` and 6 bits of `sector` are stored.
+    pub fn new(cylinder: u16, head: u8, sector: u8) -> CHS {
+        CHS {
+            head,
+            sector_cylinder_hi: (sector & 0x3F) | ((cylinder >> 2) & 0xC0) as u8,
+            cylinder_lo: cylinder as u8,
+        }
+    }
+
+    /// Returns the CHS address of `lba` on a disk with 255 heads and 63
+    /// sectors per track. LBAs that are too large map to the largest address,
+    /// 1023/254/63.
+    pub fn from_lba(lba: u64) -> CHS {
+        let cylinder = lba / (HEADS * SECTORS_PER_TRACK);
+        if cylinder > 1023 {
+            return CHS::new(1023, 254, 63);
+        }
+
+        let head = (lba / SECTORS_PER_TRACK) % HEADS;
+        let sector = lba % SECTORS_PER_TRACK + 1;
+        CHS::new(cylinder as u16, head as u8, sector as u8)
+    }
+
+    pub fn head(&self) -> u8 {
+        self.head
+    }
+
+    /// The sector, numbered from 1.
+    pub fn sector(&self) -> u8 {
+        self.sector_cylinder_hi & 0x3F
+    }
+
+    pub fn cylinder(&self) -> u16 {
+        ((self.sector_cylinder_hi as u16 & 0xC0) << 2) | self.cylinder_lo as u16
+    }
+}
+
+impl fmt::Debug for CHS {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CHS")
+            .field("cylinder", &self.cylinder())
+            .field("head", &self.head())
+            .field("sector", &self.sector())
+            .finish()
     }
 }
@@ -4,7 +4,10 @@ use std::io::prelude::*;
 use std::io::Cursor;
 use std::path::Path;
 
-use vfat::{Shared, VFat, BiosParameterBlock, CachedDevice, Partition};
+use vfat::{Shared, VFat, BiosParameterBlock, FsInfo, CachedDevice, Partition, Problem};
+use vfat::{FatType, Cluster, Status, MountOptions, MirrorCheck};
+use vfat::{Attributes, Timestamp as FatTimestamp};
+use exfat::{ExFat, BootSector};
 use mbr::{MasterBootRecord, CHS, PartitionEntry};
 use traits::*;
 
@@ -57,13 +60,29 @@ macro vfat_from_resource($name:expr) {
     VFat::from(resource!($name)).expect("failed to initialize VFAT from image")
 }
 
-// TODO: is this necessary if we aren't writing/partitioning?
-// #[test]
-// fn check_mbr_size() {
-//     check_size!(MasterBootRecord, 512);
-//     check_size!(PartitionEntry, 16);
-//     check_size!(CHS, 3);
-// }
+/// The size of the disks made by `formatted_disk!` in most tests: the
+/// smallest whole number of MiB with room for a FAT32 partition after the
+/// first MiB.
+const DISK_SIZE: usize = 34 << 20;
+
+macro formatted_disk($size:expr) {{
+    let disk = SharedDisk::new($size);
+    ::vfat::format(disk.clone(), $size as u64).expect("format disk");
+    disk
+}}
+
+macro disk_from_resource($name:expr) {{
+    let mut data = Vec::new();
+    resource!($name).read_to_end(&mut data).expect("read resource data");
+    SharedDisk::from(data)
+}}
+
+#[test]
+fn check_mbr_size() {
+    check_size!(MasterBootRecord, 512);
+    check_size!(PartitionEntry, 16);
+    check_size!(CHS, 3);
+}
 
 #[test]
 fn check_mbr_signature() {
@@ -91,6 +110,69 @@ fn check_mbr_boot_indicator() {
     MasterBootRecord::from(Cursor::new(&mut data[..])).unwrap();
 }
 
+#[test]
+fn check_mbr_partitions() {
+    let mut data = [0u8; 512];
+    data[510..].copy_from_slice(&[0x55, 0xAA]);
+    data[446..462].copy_from_slice(&[
+        0x80, 0x01, 0x01, 0x00, 0x0C, 0xFE, 0xFF, 0x2F,
+        0x3F, 0x00, 0x00, 0x00, 0xC1, 0x7F, 0x0F, 0x00
+    ]);
+    data[462 + 4] = 0x83;
+
+    let mbr = MasterBootRecord::from(Cursor::new(&mut data[..])).unwrap();
+    let partitions = mbr.partitions();
+    assert!(partitions[0].bootable() && partitions[0].is_fat());
+    assert_eq!(partitions[0].partition_type(), 0x0C);
+    assert_eq!(partitions[0].relative_sector(), 63);
+    assert_eq!(partitions[0].total_sectors(), 0xF7FC1);
+    assert_eq!(partitions[0].last_sector(), Some(63 + 0xF7FC1 - 1));
+
+    let (start, end) = (partitions[0].start_chs(), partitions[0].end_chs());
+    assert_eq!((start.cylinder(), start.head(), start.sector()), (0, 1, 1));
+    assert_eq!((end.cylinder(), end.head(), end.sector()), (0x32F, 0xFE, 0x3F));
+
+    assert!(!partitions[1].bootable() && partitions[1].is_used() && !partitions[1].is_fat());
+    assert!(!partitions[2].is_used() && !partitions[3].is_used());
+    assert_eq!(mbr.get_fat_partition_offset(), Some(63));
+}
+
+#[test]
+fn test_mbr_editing() {
+    use mbr::Error;
+
+    let mut mbr = MasterBootRecord::new();
+    let mut boot = PartitionEntry::new(0x0C, 2048, 8192);
+    boot.set_bootable(true);
+    mbr.set_partition(0, boot).expect("first partition");
+    mbr.set_partition(1, PartitionEntry::new(0x83, 10240, 4096)).expect("second partition");
+    expect_variant!(mbr.set_partition(2, PartitionEntry::new(0x83, 14335, 10)), Err(Error::Overlap(1, 2)));
+    expect_variant!(mbr.set_partition(2, PartitionEntry::new(0x83, 0, 10)), Err(Error::OutOfBounds(2)));
+    expect_variant!(mbr.resize_partition(0, 8193), Err(Error::Overlap(0, 1)));
+    assert_eq!(mbr.partitions()[0].total_sectors(), 8192);
+    assert!(!mbr.partitions()[2].is_used());
+
+    let end = mbr.partitions()[1].end_chs();
+    assert_eq!((end.cylinder(), end.head(), end.sector()), (0, 227, 35));
+
+    let mut data = vec![0xAB; 512];
+    expect_variant!(mbr.write(Cursor::new(&mut data[..]), 14335), Err(Error::OutOfBounds(1)));
+    mbr.write(Cursor::new(&mut data[..]), 14336).expect("write MBR");
+
+    data[0] = 0xEB;
+    let mut mbr = MasterBootRecord::from(Cursor::new(&mut data[..])).expect("valid MBR");
+    assert!(mbr.partitions()[0].bootable() && mbr.partitions()[0].is_fat());
+    assert_eq!(mbr.partitions()[1].relative_sector(), 10240);
+
+    mbr.remove_partition(1);
+    mbr.resize_partition(0, 12288).expect("grow partition");
+    mbr.write(Cursor::new(&mut data[..]), 14336).expect("write MBR");
+    let mbr = MasterBootRecord::from(Cursor::new(&mut data[..])).expect("valid MBR");
+    assert_eq!(mbr.partitions()[0].last_sector(), Some(2048 + 12288 - 1));
+    assert!(!mbr.partitions()[1].is_used());
+    assert_eq!(data[0], 0xEB, "bootstrap code not preserved");
+}
+
 #[test]
 fn test_mbr() {
     let mut mbr = resource!("mbr.img");
@@ -128,6 +210,11 @@ fn test_ebpb() {
     BiosParameterBlock::from(Cursor::new(&mut data[..]), 1).expect("valid EBPB");
 }
 
+#[test]
+fn check_fsinfo_size() {
+    check_size!(FsInfo, 512);
+}
+
 #[test]
 fn check_entry_sizes() {
     check_size!(::vfat::dir::VFatRegularDirEntry, 32);
@@ -345,8 +432,1424 @@ fn test_mock4_files_recursive() {
     assert_hash_eq!("mock 4 file hashes", hash, hash_for!("files-2-3-4"));
 }
 
+/// An in-memory disk whose contents remain observable after it is moved into
+/// a `CachedDevice`.
+#[derive(Clone)]
+struct SharedDisk(::std::sync::Arc<::std::sync::Mutex<Vec<u8>>>);
+
+impl SharedDisk {
+    fn new(size: usize) -> SharedDisk {
+        SharedDisk::from(vec![0; size])
+    }
+
+    fn from(data: Vec<u8>) -> SharedDisk {
+        SharedDisk(::std::sync::Arc::new(::std::sync::Mutex::new(data)))
+    }
+
+    fn bytes(&self, start: usize, end: usize) -> Vec<u8> {
+        self.0.lock().unwrap()[start..end].to_vec()
+    }
+}
+
+impl BlockDevice for SharedDisk {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        Cursor::new(&mut self.0.lock().unwrap()[..]).read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> ::std::io::Result<usize> {
+        Cursor::new(&mut self.0.lock().unwrap()[..]).write_sector(n, buf)
+    }
+}
+
+#[test]
+fn test_cache_write_back() {
+    let disk = SharedDisk::new(4 * 512);
+    let partition = Partition { start: 1, sector_size: 1024 };
+    let mut cache = CachedDevice::new(disk.clone(), partition);
+
+    cache.write_sector(0, &[0xAB; 512]).expect("write physical sector");
+    cache.get_mut(1).expect("logical sector")[1020..].copy_from_slice(&[1, 2, 3, 4]);
+    assert_eq!(cache.get(1).expect("logical sector")[1020..], [1, 2, 3, 4]);
+    assert!(disk.bytes(0, 2048).iter().all(|&b| b == 0), "written before sync");
+
+    cache.sync().expect("sync");
+    assert!(disk.bytes(0, 512).iter().all(|&b| b == 0xAB));
+    assert_eq!(disk.bytes(1532, 1536), [1, 2, 3, 4]);
+    assert!(disk.bytes(1536, 2048).iter().all(|&b| b == 0));
+}
+
+#[test]
+fn test_cache_lru_eviction() {
+    let disk = SharedDisk::new(4 * 512);
+    let partition = Partition { start: 0, sector_size: 512 };
+    let mut cache = CachedDevice::with_capacity(disk.clone(), partition, 2);
+
+    cache.get_mut(0).expect("sector")[0] = 7;
+    cache.get(1).expect("sector");
+    cache.get(0).expect("sector");
+    assert!(disk.bytes(0, 1) == [0]);
+
+    // sector 1 is least recently used, then sector 0
+    cache.get(2).expect("sector");
+    cache.get(3).expect("sector");
+    assert!(disk.bytes(0, 1) == [7], "dirty sector not written back on eviction");
+    assert_eq!(cache.get(0).expect("sector")[0], 7);
+
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 5, 3));
+}
+
 #[test]
 fn shared_fs_is_sync_send_static() {
     fn f<T: Sync + Send + 'static>() {  }
     f::<Shared<VFat>>();
 }
+
+fn first_nonempty_file<P: AsRef<Path>>(vfat: &Shared<VFat>, path: P) -> Option<::std::path::PathBuf> {
+    let path = path.as_ref();
+    for entry in vfat.open_dir(path).expect("directory").entries().expect("entries") {
+        if entry.is_file() && entry.metadata().size() > 0 {
+            return Some(path.join(entry.name()));
+        } else if entry.is_dir() && entry.name() != "." && entry.name() != ".." {
+            if let Some(found) = first_nonempty_file(vfat, path.join(entry.name())) {
+                return Some(found);
+            }
+        }
+    }
+    None
+}
+
+fn read_all<T: File>(mut file: T) -> Vec<u8> {
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).expect("read file");
+    data
+}
+
+#[test]
+fn test_file_write_overwrites_and_grows() {
+    let disk = disk_from_resource!("mock1.fat32.img");
+    let vfat = &VFat::from(disk.clone()).expect("mount");
+    let path = first_nonempty_file(vfat, "/").expect("a non-empty file");
+
+    let mut file = vfat.open_file(&path).expect("file");
+    let original = read_all(vfat.open_file(&path).expect("file"));
+    let data: Vec<u8> = (0..original.len() + 70000).map(|i| (i % 251) as u8).collect();
+    file.write_all(&data[..10]).expect("overwrite");
+    assert_eq!(file.size(), original.len() as u64);
+    file.write_all(&data[10..]).expect("grow");
+    assert_eq!(file.size(), data.len() as u64);
+    file.sync().expect("sync");
+
+    let remounted = &VFat::from(disk).expect("remount");
+    let file = remounted.open_file(&path).expect("file");
+    assert_eq!(file.size(), data.len() as u64);
+    assert!(read_all(file) == data, "written data does not match");
+}
+
+#[test]
+fn test_create_file_and_dir() {
+    use std::io::ErrorKind::{AlreadyExists, InvalidInput};
+
+    let disk = disk_from_resource!("mock1.fat32.img");
+    let vfat = &VFat::from(disk.clone()).expect("mount");
+
+    let mut file = vfat.create_file("/Newly Created File.txt").expect("create file");
+    file.write_all(b"hello, world").expect("write");
+    file.sync().expect("sync");
+    expect_variant!(vfat.create_file("/newly created file.TXT"), Err(ref e) if e.kind() == AlreadyExists);
+    expect_variant!(vfat.create_dir("/q/r", false), Err(ref e) if e.kind() == InvalidInput);
+
+    let dir = vfat.create_dir("/x/y/z", true).expect("create dirs");
+    for i in 0..200 {
+        vfat.create_file(format!("/x/y/z/file number {}", i)).expect("create file");
+    }
+    vfat.create_file("/x/y/SHORT.TXT").expect("create file");
+    vfat.borrow_mut().sync().expect("sync");
+
+    let remounted = &VFat::from(disk).expect("remount");
+    let data = read_all(remounted.open_file("/newly CREATED file.txt").expect("file"));
+    assert_eq!(&data[..], b"hello, world");
+
+    let entries: Vec<_> = remounted.open_dir("/x/y/z").expect("dir").entries().expect("entries").collect();
+    assert_eq!(entries.len(), 202);
+    for i in 0..200 {
+        let name = format!("file number {}", i);
+        assert!(entries.iter().any(|e| e.name() == name), "missing {}", name);
+    }
+
+    let parent = remounted.open_dir("/x/y").expect("dir");
+    let dotdot = entries.into_iter().find(|e| e.name() == "..").expect("..").into_dir().unwrap();
+    assert_eq!(dotdot.start_cluster, parent.start_cluster);
+    assert_eq!(dir.start_cluster, remounted.open_dir("/x/y/z").expect("dir").start_cluster);
+    assert!(parent.entries().expect("entries").any(|e| e.name() == "SHORT.TXT"));
+}
+
+#[test]
+fn test_rename_into_itself() {
+    use std::io::ErrorKind::InvalidInput;
+
+    let disk = formatted_disk!(DISK_SIZE);
+    let vfat = &VFat::from(disk).expect("mount");
+    vfat.create_dir("/a/b/c", true).expect("create dirs");
+    vfat.create_dir("/x", false).expect("create dir");
+
+    for &to in ["/a/d", "/A/d", "/a/./d", "/x/../a/d", "/a/b/c/d"].iter() {
+        expect_variant!(vfat.rename("/a", to), Err(ref e) if e.kind() == InvalidInput);
+    }
+    expect_variant!(vfat.rename("/a/b", "/A/B/C/d"), Err(ref e) if e.kind() == InvalidInput);
+    vfat.rename("/a/b/c", "/a/c").expect("move up");
+    vfat.rename("/a/b", "/x/b").expect("move across");
+    assert!(vfat.open("/x/b").is_ok());
+}
+
+#[test]
+fn test_rename_and_remove() {
+    use std::io::ErrorKind::{AlreadyExists, NotFound, Other};
+
+    let disk = disk_from_resource!("mock1.fat32.img");
+    let vfat = &VFat::from(disk.clone()).expect("mount");
+
+    vfat.create_dir("/tree/a/b", true).expect("create dirs");
+    vfat.create_dir("/tree/c", false).expect("create dir");
+    let mut file = vfat.create_file("/tree/a/b/data.bin").expect("create file");
+    let data: Vec<u8> = (0..20000).map(|i| (i % 253) as u8).collect();
+    file.write_all(&data).expect("write");
+    file.sync().expect("sync");
+
+    vfat.rename("/tree/a/b/data.bin", "/tree/c/Moved Data.bin").expect("move file");
+    expect_variant!(vfat.open("/tree/a/b/data.bin"), Err(ref e) if e.kind() == NotFound);
+    assert!(read_all(vfat.open_file("/tree/c/moved data.bin").expect("file")) == data);
+
+    vfat.rename("/tree/a", "/tree/c/a").expect("move dir");
+    expect_variant!(vfat.rename("/tree/c", "/tree/c/a/c"), Err(_));
+    expect_variant!(vfat.rename("/tree/c/moved data.bin", "/tree/c/a"), Err(ref e) if e.kind() == AlreadyExists);
+    let moved = vfat.open_dir("/tree/c/a").expect("dir");
+    let dotdot = moved.entries().expect("entries").find(|e| e.name() == "..").expect("..");
+    assert_eq!(dotdot.into_dir().unwrap().start_cluster, vfat.open_dir("/tree/c").unwrap().start_cluster);
+
+    expect_variant!(vfat.remove("/tree/c", false), Err(ref e) if e.kind() == Other);
+    vfat.remove("/tree/c/a/b", false).expect("remove empty dir");
+    vfat.remove("/tree", true).expect("remove tree");
+    vfat.borrow_mut().sync().expect("sync");
+
+    let remounted = &VFat::from(disk).expect("remount");
+    expect_variant!(remounted.open("/tree"), Err(ref e) if e.kind() == NotFound);
+    let mut file = remounted.create_file("/again.bin").expect("create file");
+    file.write_all(&data).expect("write after free");
+}
+
+#[test]
+fn test_file_seek_then_read() {
+    use std::io::SeekFrom;
+
+    let vfat = &vfat_from_resource!("mock1.fat32.img");
+    let path = first_nonempty_file(vfat, "/").expect("a non-empty file");
+    let data = read_all(vfat.open_file(&path).expect("file"));
+
+    let mut file = vfat.open_file(&path).expect("file");
+    let len = data.len() as u64;
+    for &offset in [len / 2, len - 1, 0, len / 3, len].iter() {
+        assert_eq!(file.seek(SeekFrom::Start(offset)).expect("seek"), offset);
+        let mut buf = vec![0; 1000];
+        let read = file.read(&mut buf).expect("read");
+        let end = ::std::cmp::min(offset as usize + 1000, data.len());
+        assert_eq!(&buf[..read], &data[offset as usize..end]);
+    }
+
+    assert_eq!(file.seek(SeekFrom::End(-1)).expect("seek"), len - 1);
+    expect_variant!(file.seek(SeekFrom::End(1)), Err(_));
+    expect_variant!(file.seek(SeekFrom::Current(-(len as i64))), Err(_));
+}
+
+#[test]
+fn test_statfs_tracks_allocation() {
+    let disk = disk_from_resource!("mock2.fat32.img");
+    let vfat = &VFat::from(disk.clone()).expect("mount");
+    let before = vfat.borrow_mut().statfs().expect("statfs");
+    assert!(before.free_clusters <= before.total_clusters);
+
+    let mut file = vfat.create_file("/statfs.bin").expect("create file");
+    file.write_all(&vec![0xAB; before.cluster_size as usize * 3]).expect("write");
+    file.sync().expect("sync");
+
+    // creating the entry may also have grown the root directory by a cluster
+    let after = vfat.borrow_mut().statfs().expect("statfs");
+    let used = before.free_clusters - after.free_clusters;
+    assert!(used == 3 || used == 4, "{} clusters used", used);
+
+    let remounted = &VFat::from(disk).expect("remount");
+    assert_eq!(remounted.borrow_mut().statfs().expect("statfs"), after);
+    remounted.remove("/statfs.bin", false).expect("remove");
+    assert_eq!(remounted.borrow_mut().statfs().expect("statfs").free_clusters,
+               after.free_clusters + 3);
+}
+
+#[test]
+fn test_check_and_repair() {
+    use std::path::PathBuf;
+    use vfat::Status;
+
+    let vfat = &VFat::from(disk_from_resource!("mock3.fat32.img")).expect("mount");
+    vfat.borrow_mut().repair().expect("initial repair");
+    assert_eq!(vfat.borrow_mut().check().expect("check"), vec![]);
+
+    let cluster_size = vfat.borrow().cluster_size();
+    let mut chains = Vec::new();
+    for &(name, clusters) in [("/a.bin", 3), ("/b.bin", 2), ("/c.bin", 2)].iter() {
+        let mut file = vfat.create_file(name).expect("create file");
+        file.write_all(&vec![clusters as u8; clusters * cluster_size]).expect("write");
+        file.sync().expect("sync");
+
+        let mut vfat = vfat.borrow_mut();
+        let mut chain = vec![file.start_cluster];
+        while let Some(next) = vfat.next_cluster(*chain.last().unwrap()).unwrap() {
+            chain.push(next);
+        }
+        chains.push(chain);
+    }
+
+    let lost = {
+        let mut vfat = vfat.borrow_mut();
+        vfat.set_fat_entry(chains[1][1], Status::Data(chains[0][1])).unwrap();
+        vfat.set_fat_entry(chains[2][1], Status::Free).unwrap();
+        vfat.alloc_cluster(None).unwrap()
+    };
+
+    let expected = vec![
+        Problem::CrossLinked {
+            cluster: chains[0][1].0,
+            first: PathBuf::from("/a.bin"),
+            second: PathBuf::from("/b.bin")
+        },
+        Problem::BrokenChain { path: PathBuf::from("/c.bin"), cluster: chains[2][1].0 },
+        Problem::LostCluster(lost.0),
+    ];
+    assert_eq!(vfat.borrow_mut().check().expect("check"), expected);
+    assert_eq!(vfat.borrow_mut().repair().expect("repair"), expected);
+    assert_eq!(vfat.borrow_mut().check().expect("check"), vec![]);
+
+    assert_eq!(read_all(vfat.open_file("/b.bin").unwrap()), vec![2; 2 * cluster_size]);
+    assert_eq!(read_all(vfat.open_file("/c.bin").unwrap()), vec![2; 2 * cluster_size]);
+    assert_eq!(vfat.borrow_mut().fat_entry(lost).unwrap().status(), Status::Free);
+}
+
+#[test]
+fn test_repair_deletes_directory_without_clusters() {
+    use std::path::PathBuf;
+
+    let vfat = &VFat::from(formatted_disk!(DISK_SIZE)).expect("mount");
+    let mut file = vfat.create_file("/file.bin").expect("create file");
+    file.write_all(&[1; 512]).expect("write");
+    file.sync().expect("sync");
+    let dir = vfat.create_dir("/a long directory name/inner", true).expect("create dirs");
+    let parent = vfat.open_dir("/a long directory name").expect("open dir");
+
+    // point the directory at the file's cluster, leaving it with no clusters
+    // of its own once the cross-link is repaired
+    let location = parent.location.unwrap();
+    {
+        let mut vfat = vfat.borrow_mut();
+        let mut raw = vfat.read_dir_entry(location.dir, location.regular).unwrap();
+        let cluster = file.start_cluster.0;
+        raw[20..22].copy_from_slice(&[(cluster >> 16) as u8, (cluster >> 24) as u8]);
+        raw[26..28].copy_from_slice(&[cluster as u8, (cluster >> 8) as u8]);
+        vfat.write_dir_entry(location.dir, location.regular, &raw).unwrap();
+    }
+
+    let expected = vec![
+        Problem::CrossLinked {
+            cluster: file.start_cluster.0,
+            first: PathBuf::from("/file.bin"),
+            second: PathBuf::from("/a long directory name")
+        },
+        Problem::LostCluster(parent.start_cluster.0),
+        Problem::LostCluster(dir.start_cluster.0),
+    ];
+    assert_eq!(vfat.borrow_mut().repair().expect("repair"), expected);
+    assert_eq!(vfat.borrow_mut().check().expect("check"), vec![]);
+
+    for index in location.start..location.regular + 1 {
+        assert_eq!(vfat.borrow_mut().read_dir_entry(location.dir, index).unwrap()[0], 0xE5);
+    }
+    expect_variant!(vfat.open_dir("/a long directory name"), Err(_));
+    let names: Vec<String> = walk(&vfat, "/").expect("walk")
+        .map(|item| item.expect("walk entry").1.name().to_string())
+        .collect();
+    assert_eq!(names, vec!["file.bin"]);
+}
+
+#[test]
+fn test_format() {
+    use vfat::{format_with, FormatOptions};
+
+    let mut data = vec![0; DISK_SIZE];
+    ::vfat::format(Cursor::new(&mut data[..]), DISK_SIZE as u64).expect("format");
+    let vfat = &VFat::from(Cursor::new(data)).expect("mount formatted disk");
+    let stats = vfat.borrow_mut().statfs().expect("statfs");
+    assert_eq!(stats.cluster_size, 512);
+    assert_eq!(stats.free_clusters, stats.total_clusters - 1);
+    assert!(stats.total_bytes() > 32 << 20);
+    assert_eq!(vfat.open_dir("/").expect("root").entries().expect("entries").count(), 0);
+    assert_eq!(vfat.borrow_mut().check().expect("check"), vec![]);
+
+    let mut data = vec![0; 300 << 20];
+    let options = FormatOptions { sectors_per_cluster: Some(4), ..Default::default() };
+    format_with(Cursor::new(&mut data[..]), 300 << 20, &options).expect("format");
+    let vfat = &VFat::from(Cursor::new(data)).expect("mount formatted disk");
+    assert_eq!(vfat.borrow_mut().statfs().expect("statfs").cluster_size, 2048);
+
+    let mut data = vec![0; 300 << 20];
+    let options = FormatOptions { sectors_per_cluster: Some(16), ..Default::default() };
+    expect_variant!(format_with(Cursor::new(&mut data[..]), 300 << 20, &options), Err(_));
+
+    // volumes with too few clusters for FAT32 would be read as FAT12 or FAT16
+    for &size in [1 << 20, 16 << 20, 33 << 20].iter() {
+        let mut data = vec![0; size];
+        let result = ::vfat::format(Cursor::new(&mut data[..]), size as u64);
+        expect_variant!(result, Err(ref e) if e.kind() == ::std::io::ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn test_formatted_disk_is_writable() {
+    let disk = formatted_disk!(DISK_SIZE);
+    let vfat = &VFat::from(disk.clone()).expect("mount");
+    vfat.create_dir("/a/b", true).expect("create dirs");
+    let mut file = vfat.create_file("/a/b/hello.txt").expect("create file");
+    file.write_all(b"hello, world").expect("write");
+    file.sync().expect("sync");
+
+    let vfat = &VFat::from(disk).expect("remount");
+    assert_eq!(read_all(vfat.open_file("/a/b/hello.txt").expect("file")), b"hello, world");
+    assert_eq!(vfat.borrow_mut().check().expect("check"), vec![]);
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(::util::crc32(b""), 0);
+    assert_eq!(::util::crc32(b"123456789"), 0xCBF43926);
+}
+
+/// Returns a disk of `size` bytes with a protective MBR and a GPT with a single
+/// Microsoft basic data partition formatted as FAT32.
+fn gpt_formatted_disk(size: usize) -> SharedDisk {
+    use gpt::Guid;
+    use util::{crc32, to_le};
+
+    fn le64(value: u64) -> Vec<u8> {
+        let mut bytes = to_le(value as u32).to_vec();
+        bytes.extend_from_slice(&to_le((value >> 32) as u32));
+        bytes
+    }
+
+    // Leave room for the backup GPT at the end of the disk.
+    let disk = SharedDisk::new(size);
+    ::vfat::format(disk.clone(), (size - 33 * 512) as u64).expect("format disk");
+
+    let last_lba = (size / 512 - 1) as u64;
+    let mut data = disk.0.lock().unwrap();
+    for byte in data[446..510].iter_mut() {
+        *byte = 0;
+    }
+    data[446 + 4] = 0xEE;
+    data[446 + 8..446 + 12].copy_from_slice(&to_le(1));
+    data[446 + 12..446 + 16].copy_from_slice(&to_le(last_lba as u32));
+
+    let mut entries = vec![0u8; 128 * 128];
+    entries[..16].copy_from_slice(&Guid::MICROSOFT_BASIC_DATA.0);
+    entries[16..32].copy_from_slice(&[0x42; 16]);
+    entries[32..40].copy_from_slice(&le64(2048));
+    entries[40..48].copy_from_slice(&le64(last_lba - 33));
+    entries[56..62].copy_from_slice(&[b'F', 0, b'A', 0, b'T', 0]);
+
+    for &(my_lba, alternate_lba, entries_lba) in
+            [(1, last_lba, 2), (last_lba, 1, last_lba - 32)].iter() {
+        let mut header = Vec::new();
+        header.extend_from_slice(b"EFI PART");
+        header.extend_from_slice(&to_le(0x00010000));
+        header.extend_from_slice(&to_le(92));
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&le64(my_lba));
+        header.extend_from_slice(&le64(alternate_lba));
+        header.extend_from_slice(&le64(34));
+        header.extend_from_slice(&le64(last_lba - 33));
+        header.extend_from_slice(&[0x17; 16]);
+        header.extend_from_slice(&le64(entries_lba));
+        header.extend_from_slice(&to_le(128));
+        header.extend_from_slice(&to_le(128));
+        header.extend_from_slice(&to_le(crc32(&entries)));
+        let header_crc = crc32(&header);
+        header[16..20].copy_from_slice(&to_le(header_crc));
+
+        let start = my_lba as usize * 512;
+        data[start..start + 92].copy_from_slice(&header);
+        let start = entries_lba as usize * 512;
+        data[start..start + entries.len()].copy_from_slice(&entries);
+    }
+    drop(data);
+    disk
+}
+
+#[test]
+fn test_gpt() {
+    use gpt::{self, Guid, GuidPartitionTable};
+
+    let size = DISK_SIZE;
+    let disk = gpt_formatted_disk(size);
+    let gpt = GuidPartitionTable::from(disk.clone()).expect("valid GPT");
+    assert!(!gpt.from_backup());
+    assert_eq!(gpt.partitions().len(), 1);
+    assert_eq!(gpt.partitions()[0].name(), "FAT");
+    assert!(gpt.partitions()[0].type_guid == Guid::MICROSOFT_BASIC_DATA);
+    assert_eq!(gpt.get_fat_partition_offset(), Some(2048));
+
+    let vfat = &VFat::from(disk.clone()).expect("mount GPT disk");
+    vfat.create_file("/on gpt.txt").expect("create file");
+
+    // Corrupt the primary header; the backup is used instead.
+    disk.0.lock().unwrap()[512 + 40] ^= 0xFF;
+    assert!(GuidPartitionTable::from(disk.clone()).expect("backup GPT").from_backup());
+    VFat::from(disk.clone()).expect("mount from backup GPT");
+
+    // Corrupt the backup partition entries too.
+    disk.0.lock().unwrap()[size - 33 * 512] ^= 0xFF;
+    expect_variant!(GuidPartitionTable::from(disk.clone()), Err(gpt::Error::BadHeader));
+    expect_variant!(VFat::from(disk.clone()), Err(::vfat::Error::Gpt(gpt::Error::BadHeader)));
+
+    // An empty protective partition has no last LBA to find the backup at.
+    disk.0.lock().unwrap()[446 + 8..446 + 16].copy_from_slice(&[0; 8]);
+    expect_variant!(GuidPartitionTable::from(disk), Err(gpt::Error::BadHeader));
+}
+
+#[test]
+fn test_mount_partition_and_superfloppy() {
+    let size = DISK_SIZE;
+    let disk = formatted_disk!(size);
+    VFat::from_partition(disk.clone(), 0).expect("mount partition 0");
+    expect_variant!(VFat::from_partition(disk.clone(), 1), Err(::vfat::Error::NotFound));
+    expect_variant!(VFat::from_partition(disk.clone(), 4), Err(::vfat::Error::NotFound));
+
+    {
+        let vfat = &VFat::from(disk.clone()).expect("mount");
+        let mut file = vfat.create_file("/floppy.txt").expect("create file");
+        file.write_all(b"no partition table").expect("write");
+        file.sync().expect("sync");
+    }
+
+    // The partition on its own, with its boot sector at sector 0.
+    let floppy = SharedDisk::from(disk.bytes(1 << 20, size));
+    let vfat = &VFat::from(floppy.clone()).expect("mount superfloppy");
+    assert_eq!(read_all(vfat.open_file("/floppy.txt").expect("file")), b"no partition table");
+    expect_variant!(VFat::from_partition(floppy, 0), Err(_));
+}
+
+/// Returns a superfloppy FAT12 or FAT16 disk of `total_sectors` 512-byte
+/// sectors with one reserved sector, two FATs of `sectors_per_fat` sectors
+/// each and a root directory region of `max_dirs` entries.
+fn small_fat_disk(
+    fat_type: FatType,
+    total_sectors: u16,
+    sectors_per_cluster: u8,
+    sectors_per_fat: u16,
+    max_dirs: u16
+) -> SharedDisk {
+    fn le16(value: u16) -> [u8; 2] {
+        [value as u8, (value >> 8) as u8]
+    }
+
+    let disk = SharedDisk::new(total_sectors as usize * 512);
+    let mut data = disk.0.lock().unwrap();
+    data[..11].copy_from_slice(b"\xEB\x3C\x90MSWIN4.1");
+    data[11..13].copy_from_slice(&le16(512));
+    data[13] = sectors_per_cluster;
+    data[14..16].copy_from_slice(&le16(1));
+    data[16] = 2;
+    data[17..19].copy_from_slice(&le16(max_dirs));
+    data[19..21].copy_from_slice(&le16(total_sectors));
+    data[21] = 0xF8;
+    data[22..24].copy_from_slice(&le16(sectors_per_fat));
+    data[38] = 0x29;
+    data[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    // the first two FAT entries hold the media byte and an end of chain mark
+    let reserved: &[u8] = match fat_type {
+        FatType::Fat12 => &[0xF8, 0xFF, 0xFF],
+        _ => &[0xF8, 0xFF, 0xFF, 0xFF]
+    };
+    for fat in 0..2 {
+        let start = (1 + fat * sectors_per_fat as usize) * 512;
+        data[start..start + reserved.len()].copy_from_slice(reserved);
+    }
+    drop(data);
+    disk
+}
+
+#[test]
+fn test_fat12_and_fat16() {
+    let volumes = [
+        (FatType::Fat12, 2880, 1, 9),
+        (FatType::Fat16, 32768, 4, 32),
+    ];
+    for &(fat_type, total_sectors, sectors_per_cluster, sectors_per_fat) in volumes.iter() {
+        let disk = small_fat_disk(fat_type, total_sectors, sectors_per_cluster,
+                                  sectors_per_fat, 224);
+        let big: Vec<u8> = (0..400 * 512).map(|i| (i % 251) as u8).collect();
+        {
+            let vfat = &VFat::from(disk.clone()).expect("mount");
+            assert_eq!(vfat.borrow().fat_type(), fat_type);
+
+            let mut file = vfat.create_file("/big.bin").expect("create file");
+            file.write_all(&big).expect("write");
+            file.sync().expect("sync");
+
+            vfat.create_dir("/some dir/sub", true).expect("create dirs");
+            let mut file = vfat.create_file("/some dir/sub/a file.txt").expect("create file");
+            file.write_all(b"in a subdirectory").expect("write");
+            file.sync().expect("sync");
+        }
+
+        let vfat = &VFat::from(disk).expect("remount");
+        let names: Vec<String> = vfat.open_dir("/").expect("root").entries().expect("entries")
+            .map(|entry| entry.name().to_string())
+            .collect();
+        assert_eq!(names, vec!["big.bin", "some dir"]);
+        assert_eq!(read_all(vfat.open_file("/big.bin").expect("file")), big);
+        assert_eq!(read_all(vfat.open_file("/some dir/sub/a file.txt").expect("file")),
+                   b"in a subdirectory");
+        assert_eq!(vfat.borrow_mut().check().expect("check"), vec![]);
+    }
+}
+
+#[test]
+fn test_fat12_entry_across_sectors() {
+    let disk = small_fat_disk(FatType::Fat12, 2880, 1, 9, 224);
+    let vfat = &VFat::from(disk.clone()).expect("mount");
+    {
+        let mut vfat = vfat.borrow_mut();
+        vfat.set_fat_entry(Cluster(340), Status::Data(Cluster(341))).expect("set entry");
+        vfat.set_fat_entry(Cluster(341), Status::Data(Cluster(0xABC))).expect("set entry");
+        vfat.set_fat_entry(Cluster(342), Status::Eoc(0xFFFFFFF)).expect("set entry");
+        assert_eq!(vfat.fat_entry(Cluster(340)).unwrap().status(), Status::Data(Cluster(341)));
+        assert_eq!(vfat.fat_entry(Cluster(341)).unwrap().status(), Status::Data(Cluster(0xABC)));
+        expect_variant!(vfat.fat_entry(Cluster(342)).unwrap().status(), Status::Eoc(_));
+        vfat.sync().expect("sync");
+    }
+
+    // cluster 341's entry is the last byte of the first FAT sector and the
+    // first byte of the second
+    assert_eq!(disk.bytes(512 + 510, 512 + 513), [0x55, 0xC1, 0xAB]);
+    assert_eq!(disk.bytes(10 * 512 + 510, 10 * 512 + 513), [0x55, 0xC1, 0xAB]);
+}
+
+#[test]
+fn test_fixed_root_directory_is_full() {
+    let disk = small_fat_disk(FatType::Fat16, 32768, 4, 32, 16);
+    let vfat = &VFat::from(disk).expect("mount");
+    for i in 0..16 {
+        vfat.create_file(format!("/FILE{}.TXT", i)).expect("create file");
+    }
+    match vfat.create_file("/FILE16.TXT") {
+        Err(e) => assert_eq!(e.kind(), ::std::io::ErrorKind::Other),
+        Ok(_) => panic!("root directory should be full")
+    }
+
+    // the cluster allocated for a directory that can't be added is freed
+    let free = vfat.borrow_mut().statfs().expect("statfs").free_clusters;
+    assert!(vfat.create_dir("/DIR16", false).is_err());
+    assert_eq!(vfat.borrow_mut().statfs().expect("statfs").free_clusters, free);
+}
+
+#[test]
+fn check_exfat_boot_sector_size() {
+    check_size!(BootSector, 512);
+}
+
+/// Upper cases the Latin-1 character `c` as the up-case table of
+/// `exfat_image()` does.
+fn exfat_upcase(c: u16) -> u16 {
+    match c {
+        0x61...0x7A => c - 0x20,
+        0xE0...0xFE if c != 0xF7 => c - 0x20,
+        _ => c
+    }
+}
+
+/// Returns the directory entries of an exFAT file entry set for `name`.
+fn exfat_entry_set(
+    name: &str,
+    attributes: u16,
+    no_fat_chain: bool,
+    first_cluster: u32,
+    size: u64,
+    valid_size: u64
+) -> Vec<u8> {
+    use util::to_le;
+
+    let name: Vec<u16> = name.encode_utf16().collect();
+    let name_entries = (name.len() + 14) / 15;
+    let mut set = vec![0u8; 32 * (2 + name_entries)];
+
+    // created 2020-05-17 13:45:31.23 UTC+1, modified a day later
+    let created = (40 << 25) | (5 << 21) | (17 << 16) | (13 << 11) | (45 << 5) | 15;
+    set[0] = 0x85;
+    set[1] = 1 + name_entries as u8;
+    set[4..6].copy_from_slice(&to_le(attributes as u32)[..2]);
+    set[8..12].copy_from_slice(&to_le(created));
+    set[12..16].copy_from_slice(&to_le(created + (1 << 16)));
+    set[16..20].copy_from_slice(&to_le(created + (1 << 16)));
+    set[20] = 123;
+    set[22] = 0x80 | 4;
+
+    let hash = name.iter()
+        .flat_map(|&c| { let c = exfat_upcase(c); vec![c as u8, (c >> 8) as u8] })
+        .fold(0u16, |hash, byte| hash.rotate_right(1).wrapping_add(byte as u16));
+    set[32] = 0xC0;
+    set[33] = if no_fat_chain { 0x03 } else { 0x01 };
+    set[35] = name.len() as u8;
+    set[36..38].copy_from_slice(&to_le(hash as u32)[..2]);
+    set[40..44].copy_from_slice(&to_le(valid_size as u32));
+    set[52..56].copy_from_slice(&to_le(first_cluster));
+    set[56..60].copy_from_slice(&to_le(size as u32));
+
+    for (i, c) in name.iter().enumerate() {
+        let entry = 64 + 32 * (i / 15);
+        set[entry] = 0xC1;
+        set[entry + 2 + 2 * (i % 15)] = *c as u8;
+        set[entry + 3 + 2 * (i % 15)] = (*c >> 8) as u8;
+    }
+
+    let checksum = set.iter().enumerate()
+        .filter(|&(i, _)| i != 2 && i != 3)
+        .fold(0u16, |sum, (_, &byte)| sum.rotate_right(1).wrapping_add(byte as u16));
+    set[2] = checksum as u8;
+    set[3] = (checksum >> 8) as u8;
+    set
+}
+
+/// Returns the data of the file `name` in `exfat_image()`.
+fn exfat_file_data(len: usize, seed: usize) -> Vec<u8> {
+    (0..len).map(|i| ((i * seed) % 251) as u8).collect()
+}
+
+/// Returns an exFAT superfloppy with 64 4KiB clusters holding a contiguous
+/// file in the root directory and a fragmented, partially written file in a
+/// subdirectory.
+fn exfat_image() -> Vec<u8> {
+    use util::to_le;
+
+    const SECTORS: usize = 32 + 64 * 8;
+    let cluster = |n: usize| (32 + (n - 2) * 8) * 512;
+    let mut data = vec![0u8; SECTORS * 512];
+
+    data[..11].copy_from_slice(b"\xEB\x76\x90EXFAT   ");
+    data[72..76].copy_from_slice(&to_le(SECTORS as u32));
+    data[80..84].copy_from_slice(&to_le(24));
+    data[84..88].copy_from_slice(&to_le(8));
+    data[88..92].copy_from_slice(&to_le(32));
+    data[92..96].copy_from_slice(&to_le(64));
+    data[96..100].copy_from_slice(&to_le(4));
+    data[100..104].copy_from_slice(&to_le(0x1234ABCD));
+    data[104..106].copy_from_slice(&[0x00, 0x01]);
+    data[108] = 9;
+    data[109] = 3;
+    data[110] = 1;
+    data[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    // FAT: bitmap, up-case table and root directory are single clusters; the
+    // file in the subdirectory is fragmented as 9 -> 8 -> 10
+    let fat = 24 * 512;
+    for &(entry, value) in [(0, 0xFFFFFFF8), (1, 0xFFFFFFFF), (2, 0xFFFFFFFF),
+                            (3, 0xFFFFFFFF), (4, 0xFFFFFFFF), (7, 0xFFFFFFFF),
+                            (9, 8), (8, 10), (10, 0xFFFFFFFF)].iter() {
+        data[fat + 4 * entry..fat + 4 * entry + 4].copy_from_slice(&to_le(value));
+    }
+
+    // clusters 2 through 10 are in use
+    data[cluster(2)] = 0xFF;
+    data[cluster(2) + 1] = 0x01;
+
+    let mut upcase: Vec<u16> = vec![0xFFFF, 0x61];
+    upcase.extend((0x61..0x7B).map(exfat_upcase));
+    upcase.extend_from_slice(&[0xFFFF, 0xE0 - 0x7B]);
+    upcase.extend((0xE0..0xFF).map(exfat_upcase));
+    let upcase: Vec<u8> = upcase.iter().flat_map(|&c| vec![c as u8, (c >> 8) as u8]).collect();
+    let upcase_checksum = upcase.iter()
+        .fold(0u32, |sum, &byte| sum.rotate_right(1).wrapping_add(byte as u32));
+    data[cluster(3)..cluster(3) + upcase.len()].copy_from_slice(&upcase);
+
+    let mut root = vec![0u8; 96];
+    root[0] = 0x83;
+    root[1] = 7;
+    for (i, c) in "Pi Card".encode_utf16().enumerate() {
+        root[2 + 2 * i] = c as u8;
+    }
+    root[32] = 0x81;
+    root[52..56].copy_from_slice(&to_le(2));
+    root[56] = 8;
+    root[64] = 0x82;
+    root[68..72].copy_from_slice(&to_le(upcase_checksum));
+    root[84..88].copy_from_slice(&to_le(3));
+    root[88] = upcase.len() as u8;
+    root.extend(exfat_entry_set("Hello.txt", 0x20, true, 5, 5000, 5000));
+    root.extend(exfat_entry_set("Ünïcode Directory Name", 0x10, false, 7, 4096, 4096));
+    data[cluster(4)..cluster(4) + root.len()].copy_from_slice(&root);
+
+    let subdir = exfat_entry_set("big.bin", 0x20, false, 9, 3 * 4096 - 100, 8000);
+    data[cluster(7)..cluster(7) + subdir.len()].copy_from_slice(&subdir);
+
+    data[cluster(5)..cluster(5) + 5000].copy_from_slice(&exfat_file_data(5000, 7));
+    let big = exfat_file_data(8000, 13);
+    for (i, &n) in [9, 8].iter().enumerate() {
+        let len = ::std::cmp::min(4096, 8000 - i * 4096);
+        data[cluster(n)..cluster(n) + len].copy_from_slice(&big[i * 4096..i * 4096 + len]);
+    }
+    data
+}
+
+#[test]
+fn test_exfat() {
+    let image = exfat_image();
+    let exfat = &ExFat::from(SharedDisk::from(image.clone())).expect("mount exFAT");
+    assert_eq!(exfat.borrow().label(), "Pi Card");
+    assert_eq!(exfat.borrow().volume_id(), 0x1234ABCD);
+    let statfs = exfat.borrow_mut().statfs().expect("statfs");
+    assert_eq!((statfs.total_clusters, statfs.free_clusters, statfs.cluster_size), (64, 55, 4096));
+
+    let names: Vec<String> = exfat.open_dir("/").expect("root").entries().expect("entries")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, vec!["Hello.txt", "Ünïcode Directory Name"]);
+
+    let hello = exfat.open("/HELLO.TXT").expect("case-insensitive lookup");
+    let created = hello.metadata().created();
+    assert_eq!((created.year(), created.month(), created.day()), (2020, 5, 17));
+    assert_eq!((created.hour(), created.minute(), created.second()), (13, 45, 31));
+    assert_eq!((created.centisecond(), created.utc_offset_minutes()), (23, Some(60)));
+    assert_eq!(hello.metadata().modified().day(), 18);
+    assert_eq!(read_all(hello.into_file().unwrap()), exfat_file_data(5000, 7));
+
+    let mut big = exfat.open_file("/üNÏCODE directory name/BIG.bin").expect("big.bin");
+    assert_eq!(big.size(), 3 * 4096 - 100);
+    let mut expected = exfat_file_data(8000, 13);
+    expected.resize(3 * 4096 - 100, 0);
+    let mut buf = vec![0; 300];
+    big.seek(::std::io::SeekFrom::Start(7900)).expect("seek");
+    big.read_exact(&mut buf).expect("read");
+    assert_eq!(buf, &expected[7900..8200]);
+    big.seek(::std::io::SeekFrom::Start(0)).expect("seek");
+    assert_eq!(read_all(big), expected);
+
+    let denied = ::std::io::ErrorKind::PermissionDenied;
+    expect_variant!(exfat.create_file("/new.txt"), Err(ref e) if e.kind() == denied);
+    let mut hello = exfat.open_file("/Hello.txt").expect("Hello.txt");
+    expect_variant!(hello.write(b"data"), Err(ref e) if e.kind() == denied);
+
+    // the same volume in an MBR partition of type 0x07
+    let disk = SharedDisk::new(2048 * 512 + image.len());
+    disk.0.lock().unwrap()[2048 * 512..].copy_from_slice(&image);
+    let mut mbr = MasterBootRecord::new();
+    mbr.set_partition(0, PartitionEntry::new(0x07, 2048, (image.len() / 512) as u32))
+        .expect("partition");
+    mbr.write(disk.clone(), 2048 + (image.len() / 512) as u64).expect("write MBR");
+    let exfat = &ExFat::from(disk).expect("mount exFAT partition");
+    assert_eq!(read_all(exfat.open_file("/hello.txt").expect("file")), exfat_file_data(5000, 7));
+
+    // shifts whose sum overflows a u8 are rejected
+    let mut corrupt = image.clone();
+    corrupt[108] = 12;
+    corrupt[109] = 0xFF;
+    expect_variant!(ExFat::from(SharedDisk::from(corrupt)), Err(_));
+
+    // data lengths longer than the volume are rejected before reading
+    let mut corrupt = image.clone();
+    let upcase_len = (32 + 2 * 8) * 512 + 88;
+    corrupt[upcase_len..upcase_len + 8].copy_from_slice(&[0, 0, 0, 0, 0, 1, 0, 0]);
+    expect_variant!(ExFat::from(SharedDisk::from(corrupt)), Err(_));
+}
+
+/// Returns the byte offsets on `disk` of the two FATs of a disk made by
+/// `formatted_disk!`.
+fn fat_offsets(disk: &SharedDisk) -> (usize, usize) {
+    let bpb = BiosParameterBlock::from(disk.clone(), 2048).expect("BPB");
+    let first = (2048 + bpb.num_reserved as usize) * 512;
+    (first, first + bpb.sectors_per_fat32 as usize * 512)
+}
+
+#[test]
+fn test_fat_mirror_check() {
+    let size = DISK_SIZE;
+    let disk = formatted_disk!(size);
+    let (fat0, fat1) = fat_offsets(&disk);
+    disk.0.lock().unwrap()[fat1 + 5 * 4] = 0x07;
+
+    let options = |fat_mirror| MountOptions { fat_mirror };
+    let vfat = VFat::from(disk.clone()).expect("mount");
+    assert_eq!(vfat.borrow().fat_mismatches(), &[]);
+    let vfat = VFat::from_with(disk.clone(), &options(MirrorCheck::Report)).expect("mount");
+    assert_eq!(vfat.borrow().fat_mismatches(), &[(1, Cluster(5))]);
+    assert_eq!(disk.bytes(fat1 + 20, fat1 + 21), [0x07]);
+
+    let vfat = VFat::from_with(disk.clone(), &options(MirrorCheck::Repair)).expect("mount");
+    assert_eq!(vfat.borrow().fat_mismatches(), &[(1, Cluster(5))]);
+    assert_eq!(disk.bytes(fat0, fat1), disk.bytes(fat1, fat1 + (fat1 - fat0)));
+    let vfat = VFat::from_with(disk, &options(MirrorCheck::Report)).expect("mount");
+    assert_eq!(vfat.borrow().fat_mismatches(), &[]);
+}
+
+#[test]
+fn test_fat_mirroring_disabled() {
+    let disk = formatted_disk!(DISK_SIZE);
+    let (fat0, fat1) = fat_offsets(&disk);
+    // mirroring disabled, FAT 1 active
+    disk.0.lock().unwrap()[2048 * 512 + 40] = 0x81;
+    let original_fat0 = disk.bytes(fat0, fat1);
+
+    {
+        let vfat = &VFat::from(disk.clone()).expect("mount");
+        assert!(!vfat.borrow().mirroring());
+        assert_eq!(vfat.borrow().active_fat(), 1);
+        let mut file = vfat.create_file("/one fat.txt").expect("create file");
+        file.write_all(&[0xAA; 10000]).expect("write");
+        file.sync().expect("sync");
+    }
+
+    assert_eq!(disk.bytes(fat0, fat1), original_fat0);
+    assert!(disk.bytes(fat1, fat1 + (fat1 - fat0)) != original_fat0);
+    let vfat = &VFat::from(disk).expect("remount");
+    assert_eq!(read_all(vfat.open_file("/one fat.txt").expect("file")), vec![0xAA; 10000]);
+    assert_eq!(vfat.borrow_mut().check().expect("check"), vec![]);
+}
+
+#[test]
+fn test_timestamp_conversion() {
+    let ts = FatTimestamp::new(2020, 5, 17, 13, 45, 31).expect("valid timestamp");
+    assert_eq!((ts.year(), ts.month(), ts.day()), (2020, 5, 17));
+    assert_eq!((ts.hour(), ts.minute(), ts.second()), (13, 45, 31));
+    assert_eq!(ts.to_unix(), 1589723131);
+    assert_eq!(FatTimestamp::from_unix(1589723131), Some(ts));
+    assert_eq!(ts.to_string(), "2020-05-17 13:45:31");
+    assert_eq!(FatTimestamp { centiseconds: 123, ..ts }.to_string(), "2020-05-17 13:45:31.23");
+
+    assert_eq!(FatTimestamp::EPOCH.to_unix(), 315532800);
+    assert_eq!(FatTimestamp::from_unix(315532800), Some(FatTimestamp::EPOCH));
+    assert_eq!(FatTimestamp::from_unix(0), None);
+    assert_eq!(FatTimestamp::from_unix(4354905600), None);
+    assert!(FatTimestamp::new(2024, 2, 29, 0, 0, 0).is_some());
+    assert!(FatTimestamp::new(2023, 2, 29, 0, 0, 0).is_none());
+    assert!(FatTimestamp::new(2020, 13, 1, 0, 0, 0).is_none());
+    assert!(FatTimestamp::new(2020, 1, 1, 24, 0, 0).is_none());
+    assert_eq!(FatTimestamp::from_unix(1709251199).unwrap().to_string(), "2024-02-29 23:59:59");
+
+    let later = FatTimestamp::new(2020, 5, 17, 13, 46, 0).unwrap();
+    assert!(FatTimestamp::EPOCH < ts && ts < later);
+    assert!(ts < FatTimestamp { centiseconds: 150, ..ts });
+    assert!(FatTimestamp::now() > ts);
+}
+
+#[test]
+fn test_set_file_timestamps() {
+    let disk = formatted_disk!(DISK_SIZE);
+    let created = FatTimestamp {
+        centiseconds: 187,
+        ..FatTimestamp::new(2019, 12, 31, 23, 59, 58).unwrap()
+    };
+    let modified = FatTimestamp::new(2021, 3, 4, 5, 6, 7).unwrap();
+    {
+        let vfat = &VFat::from(disk.clone()).expect("mount");
+        vfat.borrow_mut().set_clock(|| FatTimestamp::new(2022, 1, 2, 3, 4, 5).unwrap());
+        let mut file = vfat.create_file("/stamped.txt").expect("create file");
+        assert_eq!(file.metadata.created.to_string(), "2022-01-02 03:04:05");
+        file.metadata.set_created(created);
+        file.metadata.set_modified(modified);
+        file.metadata.set_accessed(modified);
+        file.sync().expect("sync");
+    }
+
+    let vfat = &VFat::from(disk).expect("remount");
+    let entry = vfat.open("/stamped.txt").expect("entry");
+    assert_eq!(entry.metadata().created(), created);
+    assert_eq!(entry.metadata().created().second(), 59);
+    assert_eq!(entry.metadata().created().centisecond(), 87);
+    assert_eq!(entry.metadata().modified().to_string(), "2021-03-04 05:06:06");
+    assert_eq!(entry.metadata().accessed().to_string(), "2021-03-04 00:00:00");
+}
+
+#[test]
+fn test_attributes() {
+    let lfn = Attributes::LFN;
+    assert!(lfn.is_lfn() && !lfn.read_only() && !lfn.volume_id());
+    let attributes = Attributes::HIDDEN | Attributes::SYSTEM | Attributes::DIRECTORY;
+    assert!(attributes.hidden() && attributes.system() && attributes.directory());
+    assert!(!attributes.is_lfn() && !attributes.archive() && !attributes.read_only());
+    assert_eq!(attributes & !Attributes::HIDDEN, Attributes::SYSTEM | Attributes::DIRECTORY);
+    assert!(Attributes(0xCF).is_lfn() && !Attributes(0x1F).is_lfn());
+}
+
+#[test]
+fn test_set_attributes_and_read_only() {
+    use std::io::ErrorKind::{InvalidInput, PermissionDenied};
+
+    let disk = formatted_disk!(DISK_SIZE);
+    {
+        let vfat = &VFat::from(disk.clone()).expect("mount");
+        let mut file = vfat.create_file("/locked.txt").expect("create file");
+        file.write_all(b"original").expect("write");
+        file.sync().expect("sync");
+        file.set_attributes(Attributes::READ_ONLY | Attributes::HIDDEN).expect("set attributes");
+        expect_variant!(file.set_attributes(Attributes::DIRECTORY), Err(ref e) if e.kind() == InvalidInput);
+
+        let mut dir = vfat.create_dir("/dir", false).expect("create dir");
+        expect_variant!(dir.set_attributes(Attributes::ARCHIVE), Err(ref e) if e.kind() == InvalidInput);
+        dir.set_attributes(Attributes::DIRECTORY | Attributes::SYSTEM).expect("set attributes");
+        vfat.borrow_mut().sync().expect("sync");
+    }
+
+    let vfat = &VFat::from(disk).expect("remount");
+    let entry = vfat.open("/locked.txt").expect("entry");
+    assert!(entry.metadata().read_only() && entry.metadata().hidden());
+    assert!(!entry.metadata().archive());
+    assert!(vfat.open("/dir").expect("entry").metadata().system());
+
+    let mut file = entry.into_file().unwrap();
+    expect_variant!(file.write(b"changed"), Err(ref e) if e.kind() == PermissionDenied);
+    expect_variant!(vfat.remove("/locked.txt", false), Err(ref e) if e.kind() == PermissionDenied);
+    file.set_attributes(Attributes::ARCHIVE).expect("set attributes");
+    file.write_all(b"changed").expect("write");
+    vfat.remove("/locked.txt", false).expect("remove");
+}
+
+#[test]
+fn test_volume_label() {
+    use std::io::ErrorKind::{InvalidInput, NotFound};
+    use vfat::{format_with, FormatOptions};
+
+    let disk = SharedDisk::new(DISK_SIZE);
+    let options = FormatOptions {
+        volume_label: *b"BOOT DISK  ",
+        volume_id: 0x1234ABCD,
+        ..Default::default()
+    };
+    format_with(disk.clone(), DISK_SIZE as u64, &options).expect("format");
+    {
+        let vfat = &VFat::from(disk.clone()).expect("mount");
+        let mut vfat = vfat.borrow_mut();
+        assert_eq!(vfat.volume_id(), Some(0x1234ABCD));
+        assert_eq!(vfat.label().expect("label"), Some("BOOT DISK".to_string()));
+        expect_variant!(vfat.set_label("a label too long"), Err(ref e) if e.kind() == InvalidInput);
+        expect_variant!(vfat.set_label("a/b"), Err(ref e) if e.kind() == InvalidInput);
+        vfat.set_label("My Data").expect("set label");
+        vfat.sync().expect("sync");
+    }
+
+    // the label in both boot sectors matches the root directory entry
+    let boot = 1 << 20;
+    for &sector in [boot, boot + 6 * 512].iter() {
+        assert_eq!(&disk.0.lock().unwrap()[sector + 71..sector + 82], b"MY DATA    ");
+    }
+    let vfat = &VFat::from(disk.clone()).expect("remount");
+    assert_eq!(vfat.borrow_mut().label().expect("label"), Some("MY DATA".to_string()));
+
+    // the label entry is not listed or found as a file
+    vfat.create_file("/notes.txt").expect("create file");
+    let names: Vec<String> = vfat.open_dir("/").expect("root").entries().expect("entries")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, vec!["notes.txt"]);
+    expect_variant!(vfat.open("/MY DATA"), Err(ref e) if e.kind() == NotFound);
+    vfat.remove("/notes.txt", false).expect("remove");
+    vfat.borrow_mut().set_label("").expect("remove label");
+    assert_eq!(vfat.borrow_mut().label().expect("label"), None);
+    vfat.borrow_mut().sync().expect("sync");
+    assert_eq!(&disk.0.lock().unwrap()[boot + 71..boot + 82], b"NO NAME    ");
+
+    // labels are upper case, so `σ` is stored as `Σ`; a first byte of 0xE5, `σ`
+    // in code page 437, is escaped like in short names
+    vfat.borrow_mut().set_label("σigma").expect("set label");
+    assert_eq!(vfat.borrow_mut().label().expect("label"), Some("ΣIGMA".to_string()));
+    {
+        let mut vfat = vfat.borrow_mut();
+        let root = vfat.root_cluster();
+        let (index, _) = ::vfat::dir::find_volume_entry(&mut vfat).unwrap().expect("entry");
+        let mut entry = vfat.read_dir_entry(root, index).unwrap();
+        entry[0] = 0x05;
+        vfat.write_dir_entry(root, index, &entry).unwrap();
+    }
+    assert_eq!(vfat.borrow_mut().label().expect("label"), Some("σIGMA".to_string()));
+
+    // FAT12 volumes keep their label at a different offset in the boot sector
+    let disk = small_fat_disk(FatType::Fat12, 2880, 1, 9, 224);
+    let vfat = &VFat::from(disk.clone()).expect("mount");
+    assert_eq!(vfat.borrow_mut().label().expect("label"), None);
+    vfat.borrow_mut().set_label("floppy").expect("set label");
+    assert_eq!(vfat.borrow_mut().label().expect("label"), Some("FLOPPY".to_string()));
+    vfat.borrow_mut().sync().expect("sync");
+    assert_eq!(&disk.0.lock().unwrap()[43..54], b"FLOPPY     ");
+}
+
+#[test]
+fn test_invalid_bpb() {
+    let corruptions: [(usize, &[u8]); 6] = [
+        (11, &[0, 0]),
+        (11, &[0, 3]),
+        (13, &[0]),
+        (13, &[3]),
+        (44, &[0, 0, 0, 0]),
+        // a FAT32 layout with 20000 sectors has too few clusters for FAT32
+        (32, &[0x20, 0x4E, 0, 0]),
+    ];
+    for &(offset, bytes) in corruptions.iter() {
+        let disk = formatted_disk!(DISK_SIZE);
+        let start = (1 << 20) + offset;
+        disk.0.lock().unwrap()[start..start + bytes.len()].copy_from_slice(bytes);
+        expect_variant!(VFat::from(disk), Err(::vfat::Error::InvalidBpb(_)));
+    }
+
+    // a FAT16 layout with 70000 sectors has too many clusters for FAT16
+    let disk = small_fat_disk(FatType::Fat16, 32768, 1, 256, 512);
+    {
+        let mut data = disk.0.lock().unwrap();
+        data[19..21].copy_from_slice(&[0, 0]);
+        data[32..36].copy_from_slice(&[0x70, 0x11, 0x01, 0x00]);
+    }
+    expect_variant!(VFat::from(disk), Err(::vfat::Error::InvalidBpb(_)));
+}
+
+#[test]
+fn test_corrupt_cluster_chains() {
+    use std::io::ErrorKind::InvalidData;
+
+    let disk = formatted_disk!(DISK_SIZE);
+    let vfat = &VFat::from(disk).expect("mount");
+    let looped = vfat.create_dir("/looped", false).expect("create dir").start_cluster;
+    let mut file = vfat.create_file("/wild.bin").expect("create file");
+    file.write_all(&[1; 2048]).expect("write");
+    file.sync().expect("sync");
+
+    vfat.borrow_mut().set_fat_entry(looped, Status::Data(looped)).expect("set entry");
+    let entries = vfat.open_dir("/looped").unwrap().entries().map(|_| ());
+    expect_variant!(entries, Err(ref e) if e.kind() == InvalidData);
+    expect_variant!(vfat.create_file("/looped/new"), Err(ref e) if e.kind() == InvalidData);
+
+    let start = file.start_cluster;
+    vfat.borrow_mut().set_fat_entry(start, Status::Data(Cluster(0x0FFFFF00))).expect("set entry");
+    let mut data = Vec::new();
+    let result = vfat.open_file("/wild.bin").unwrap().read_to_end(&mut data);
+    expect_variant!(result, Err(ref e) if e.kind() == InvalidData);
+}
+
+#[test]
+fn test_malformed_directories() {
+    let disk = formatted_disk!(DISK_SIZE);
+    let vfat = &VFat::from(disk).expect("mount");
+    vfat.create_file("/KEPT.TXT").expect("create file");
+    let root = vfat.borrow().root_cluster();
+    let entries_per_cluster = vfat.borrow().cluster_size() / 32;
+
+    let mut lfn = [0u8; 32];
+    lfn[0] = 0x41;
+    lfn[1] = b'x';
+    lfn[11] = 0x0F;
+    let mut deleted = [0u8; 32];
+    deleted[0] = 0xE5;
+    {
+        let mut vfat = vfat.borrow_mut();
+        let kept = vfat.read_dir_entry(root, 0).unwrap();
+        // an LFN entry orphaned by a deleted regular entry is skipped
+        vfat.write_dir_entry(root, 0, &lfn).unwrap();
+        vfat.write_dir_entry(root, 1, &deleted).unwrap();
+        vfat.write_dir_entry(root, 2, &kept).unwrap();
+        // the directory ends part way through a long file name
+        for index in 3..entries_per_cluster {
+            vfat.write_dir_entry(root, index, &lfn).unwrap();
+        }
+    }
+
+    let names: Vec<String> = vfat.open_dir("/").unwrap().entries().unwrap()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, vec!["KEPT.TXT"]);
+
+    assert_eq!(vfat.open_dir("/../..").expect("root").start_cluster, root);
+    assert!(vfat.open("/../kept.txt").is_ok());
+}
+
+#[test]
+fn test_short_names() {
+    let disk = formatted_disk!(DISK_SIZE);
+    let vfat = &VFat::from(disk).expect("mount");
+    let root = vfat.borrow().root_cluster();
+    let short_names = || -> Vec<([u8; 11], u8)> {
+        let mut vfat = vfat.borrow_mut();
+        (0..).map(|i| vfat.read_dir_entry(root, i))
+            .take_while(|entry| entry.is_ok())
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry[11] != 0x0F && entry[0] != 0)
+            .map(|entry| {
+                let mut short_name = [0; 11];
+                short_name.copy_from_slice(&entry[..11]);
+                (short_name, entry[12])
+            })
+            .collect()
+    };
+
+    // names that differ from an 8.3 name only in case are stored with case flags
+    vfat.create_file("/readme.txt").expect("create file");
+    vfat.create_file("/README.md").expect("create file");
+    vfat.create_file("/MixedCase.txt").expect("create file");
+    for i in 0..10 {
+        vfat.create_file(format!("/long file {}.txt", i)).expect("create file");
+    }
+    vfat.create_file("/café.txt").expect("create file");
+
+    let names = short_names();
+    assert_eq!(names[0], (*b"README  TXT", 0x18));
+    assert_eq!(names[1], (*b"README  MD ", 0x10));
+    assert_eq!(names[2], (*b"MIXEDC~1TXT", 0));
+    assert_eq!(names[3].0, *b"LONGFI~1TXT");
+    assert_eq!(names[12].0, *b"LONGF~10TXT");
+    assert_eq!(names[13].0, *b"CAF\x90~1  TXT");
+
+    let listed: Vec<String> = vfat.open_dir("/").unwrap().entries().unwrap()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(&listed[..3], &["readme.txt", "README.md", "MixedCase.txt"]);
+    assert_eq!(listed[13], "café.txt");
+
+    // short names are decoded from code page 437, with 0x05 standing for 0xE5
+    {
+        let mut vfat = vfat.borrow_mut();
+        let mut entry = vfat.read_dir_entry(root, 0).unwrap();
+        entry[..11].copy_from_slice(b"\x05A\x80     TXT");
+        entry[12] = 0;
+        vfat.write_dir_entry(root, 0, &entry).unwrap();
+    }
+    assert!(vfat.open_file("/σAÇ.TXT").is_ok());
+}
+
+#[test]
+fn test_unicode_lookup() {
+    use std::io::ErrorKind::{AlreadyExists, InvalidData, InvalidInput, NotFound};
+
+    let disk = formatted_disk!(DISK_SIZE);
+    let vfat = &VFat::from(disk).expect("mount");
+    vfat.create_file("/Bad Name.txt").expect("create file");
+    vfat.create_file("/Ä.TXT").expect("create file");
+    vfat.create_file("/Σίσυφος").expect("create file");
+    vfat.create_file("/straße").expect("create file");
+    vfat.create_file("/trailing. .").expect("create file");
+
+    assert_eq!(vfat.open("/ä.txt").expect("entry").name(), "Ä.TXT");
+    assert!(vfat.open("/σίσυφοσ").is_ok());
+    assert!(vfat.open("/ΣΊΣΥΦΟΣ").is_ok());
+    expect_variant!(vfat.create_file("/ä.txt"), Err(ref e) if e.kind() == AlreadyExists);
+    expect_variant!(vfat.open("/STRASSE"), Err(ref e) if e.kind() == NotFound);
+
+    // trailing dots and spaces are ignored, as they are on Windows
+    assert_eq!(vfat.open("/trailing").expect("entry").name(), "trailing");
+    assert!(vfat.open("/ä.txt. ").is_ok());
+    expect_variant!(vfat.create_file("/trailing "), Err(ref e) if e.kind() == AlreadyExists);
+    expect_variant!(vfat.create_file("/..."), Err(ref e) if e.kind() == InvalidInput);
+
+    // U+00FF is part of the name, not the end of it
+    vfat.create_file("/aÿb.txt").expect("create file");
+    let names: Vec<String> = vfat.open_dir("/").expect("root").entries().expect("entries")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert!(names.contains(&"aÿb.txt".to_string()), "{:?}", names);
+    assert_eq!(vfat.open("/AŸB.TXT").expect("entry").name(), "aÿb.txt");
+    expect_variant!(vfat.open("/a"), Err(ref e) if e.kind() == NotFound);
+    vfat.create_file("/a").expect("create file");
+
+    // a long file name with an unpaired surrogate doesn't match its lossy form
+    let root = vfat.borrow().root_cluster();
+    {
+        let mut vfat = vfat.borrow_mut();
+        let mut lfn = vfat.read_dir_entry(root, 0).unwrap();
+        lfn[1..3].copy_from_slice(&[0x00, 0xD8]);
+        vfat.write_dir_entry(root, 0, &lfn).unwrap();
+    }
+    expect_variant!(vfat.open("/\u{FFFD}ad Name.txt"), Err(ref e) if e.kind() == InvalidData);
+    expect_variant!(vfat.open("/Bad Name.txt"), Err(ref e) if e.kind() == NotFound);
+}
+
+#[test]
+fn test_dentry_cache() {
+    use std::io::ErrorKind::NotFound;
+
+    let disk = formatted_disk!(DISK_SIZE);
+    let vfat = &VFat::from(disk).expect("mount");
+    vfat.create_dir("/a/b/c", true).expect("create dirs");
+    vfat.create_file("/a/b/c/data.txt").expect("create file");
+
+    assert!(vfat.open("/a/b/c/data.txt").is_ok());
+    let hits = vfat.borrow().dentry_cache_stats().hits;
+    assert!(vfat.open("/A/B/C/DATA.TXT").is_ok());
+    assert_eq!(vfat.borrow().dentry_cache_stats().hits, hits + 4);
+
+    // writing an entry's metadata invalidates the cached copy
+    let mut file = vfat.open_file("/a/b/c/data.txt").expect("file");
+    file.write_all(b"cached").expect("write");
+    file.sync().expect("sync");
+    assert_eq!(vfat.open("/a/b/c/data.txt").expect("entry").metadata().size(), 6);
+
+    vfat.rename("/a/b/c/data.txt", "/a/moved.txt").expect("rename");
+    expect_variant!(vfat.open("/a/b/c/data.txt"), Err(ref e) if e.kind() == NotFound);
+    assert_eq!(read_all(vfat.open_file("/a/moved.txt").expect("file")), b"cached");
+
+    vfat.remove("/a/moved.txt", false).expect("remove");
+    expect_variant!(vfat.open("/a/moved.txt"), Err(ref e) if e.kind() == NotFound);
+    vfat.create_file("/a/moved.txt").expect("create file");
+    assert_eq!(vfat.open("/a/moved.txt").expect("entry").metadata().size(), 0);
+
+    // a new directory may reuse the first cluster of a removed one
+    assert!(vfat.open("/a/b/c/.").is_ok());
+    vfat.remove("/a/b", true).expect("remove");
+    vfat.create_dir("/d", false).expect("create dir");
+    expect_variant!(vfat.open("/a/b"), Err(ref e) if e.kind() == NotFound);
+    assert_eq!(vfat.open_dir("/d").expect("dir").entries().expect("entries").count(), 2);
+
+    // the root directory is cluster 0 when reached through a `..` entry
+    vfat.create_file("/top.txt").expect("create file");
+    let root = vfat.open_dir("/a").expect("dir").find("..").expect("..")
+        .into_dir().expect("root");
+    assert!(root.find("top.txt").is_ok());
+    vfat.remove("/top.txt", false).expect("remove");
+    expect_variant!(root.find("top.txt"), Err(ref e) if e.kind() == NotFound);
+}
+
+#[test]
+fn test_walk() {
+    let disk = formatted_disk!(DISK_SIZE);
+    let vfat = &VFat::from(disk).expect("mount");
+    for path in ["/var/log/a.log", "/var/log/old/b.log", "/var/run/c.pid", "/var/d.log"].iter() {
+        vfat.create_dir(Path::new(path).parent().unwrap(), true).ok();
+        vfat.create_file(path).expect("create file");
+    }
+    vfat.create_file("/var/log/.hidden.log").expect("create file")
+        .set_attributes(Attributes::HIDDEN).expect("hide");
+
+    let paths = |walk: Walk<::vfat::Entry>| -> Vec<String> {
+        walk.map(|item| item.expect("entry").0.to_str().unwrap().to_string()).collect()
+    };
+
+    assert_eq!(paths(walk(&vfat, "/var").expect("walk")), vec![
+        "/var/log", "/var/log/a.log", "/var/log/old", "/var/log/old/b.log",
+        "/var/log/.hidden.log", "/var/run", "/var/run/c.pid", "/var/d.log",
+    ]);
+    assert_eq!(paths(walk(&vfat, "/var").expect("walk").order(Order::BreadthFirst)), vec![
+        "/var/log", "/var/run", "/var/d.log", "/var/log/a.log", "/var/log/old",
+        "/var/log/.hidden.log", "/var/run/c.pid", "/var/log/old/b.log",
+    ]);
+
+    let logs = walk(&vfat, "/").expect("walk")
+        .skip_hidden(true)
+        .max_depth(3)
+        .matching(|path, entry| entry.is_file() && path.extension() == Some("log".as_ref()));
+    assert_eq!(paths(logs), vec!["/var/log/a.log", "/var/d.log"]);
+
+    let dots = walk(&vfat, "/var/run").expect("walk").skip_dots(false);
+    assert_eq!(paths(dots), vec!["/var/run/.", "/var/run/..", "/var/run/c.pid"]);
+    assert!(walk(&vfat, "/var/d.log").is_err());
+
+    // an unreadable directory is yielded, followed by the error from listing it
+    let run = vfat.open_dir("/var/run").expect("dir").start_cluster;
+    vfat.borrow_mut().set_fat_entry(run, Status::Data(run)).expect("set entry");
+    let items: Vec<_> = walk(&vfat, "/var").expect("walk")
+        .matching(|path, _| path.starts_with("/var/run"))
+        .map(|item| item.map(|(path, _)| path.to_str().unwrap().to_string()))
+        .collect();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].as_ref().ok().map(|path| path.as_str()), Some("/var/run"));
+    let error = items[1].as_ref().map(|_| ());
+    expect_variant!(error, Err(e) if e.kind() == ::std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_set_len_and_preallocate() {
+    use std::io::ErrorKind::{InvalidData, Other, PermissionDenied};
+
+    let disk = formatted_disk!(DISK_SIZE);
+    let vfat = &VFat::from(disk.clone()).expect("mount");
+    let cluster_size = vfat.borrow().cluster_size();
+    let free = |vfat: &Shared<VFat>| vfat.borrow_mut().statfs().expect("statfs").free_clusters;
+    let initial = free(vfat);
+
+    let mut file = vfat.create_file("/data.bin").expect("create file");
+    file.write_all(&vec![0xAA; cluster_size * 3]).expect("write");
+    file.set_len(cluster_size as u64 + 10).expect("shrink");
+    assert_eq!(file.size(), cluster_size as u64 + 10);
+    assert_eq!(free(vfat), initial - 2);
+
+    // growing zeroes the bytes past the old end, even within its last cluster
+    file.set_len(cluster_size as u64 * 2 + 5).expect("grow");
+    file.sync().expect("sync");
+    let data = read_all(vfat.open_file("/data.bin").expect("file"));
+    assert_eq!(data.len(), cluster_size * 2 + 5);
+    assert!(data[..cluster_size + 10].iter().all(|&b| b == 0xAA));
+    assert!(data[cluster_size + 10..].iter().all(|&b| b == 0));
+
+    file.set_len(0).expect("truncate");
+    assert_eq!(file.start_cluster, Cluster(0));
+    assert_eq!(free(vfat), initial);
+
+    // preallocated clusters are contiguous and don't change the size
+    let mut log = vfat.create_file("/app.log").expect("create file");
+    log.preallocate(cluster_size as u64 * 4).expect("preallocate");
+    assert_eq!(log.size(), 0);
+    assert_eq!(free(vfat), initial - 4);
+    let start = log.start_cluster.0;
+    {
+        let mut vfat = vfat.borrow_mut();
+        for raw in start..start + 3 {
+            assert_eq!(vfat.next_cluster(Cluster(raw)).unwrap(), Some(Cluster(raw + 1)));
+        }
+        assert_eq!(vfat.next_cluster(Cluster(start + 3)).unwrap(), None);
+    }
+    log.write_all(&vec![1; cluster_size * 4]).expect("write");
+    assert_eq!(free(vfat), initial - 4);
+    log.preallocate(cluster_size as u64).expect("preallocate");
+    assert_eq!(free(vfat), initial - 4);
+
+    // a chain that loops back on itself is an error
+    vfat.borrow_mut().set_fat_entry(Cluster(start + 3), Status::Data(Cluster(start))).unwrap();
+    let result = log.preallocate(cluster_size as u64 * 8);
+    expect_variant!(result, Err(ref e) if e.kind() == InvalidData);
+    vfat.borrow_mut().set_fat_entry(Cluster(start + 3), Status::Eoc(0x0FFFFFFF)).unwrap();
+
+    // clusters allocated before the disk fills up stay in the file's chain
+    let mut big = vfat.create_file("/big.bin").expect("create file");
+    let free_clusters = free(vfat) as u64;
+    let result = big.preallocate(cluster_size as u64 * (free_clusters + 1));
+    expect_variant!(result, Err(ref e) if e.kind() == Other);
+    assert_eq!(free(vfat), 0);
+    big.set_len(0).expect("truncate");
+    assert_eq!(free(vfat) as u64, free_clusters);
+
+    log.set_attributes(Attributes::READ_ONLY).expect("set attributes");
+    expect_variant!(log.set_len(0), Err(ref e) if e.kind() == PermissionDenied);
+}
+
+#[test]
+fn test_stale_free_count() {
+    let disk = formatted_disk!(DISK_SIZE);
+    let fsinfo = (1 << 20) + 512;
+    disk.0.lock().unwrap()[fsinfo + 488..fsinfo + 492].copy_from_slice(&[0; 4]);
+
+    let vfat = &VFat::from(disk).expect("mount");
+    assert_eq!(vfat.borrow_mut().statfs().expect("statfs").free_clusters, 0);
+    let mut file = vfat.create_file("/data.bin").expect("create file");
+    file.write_all(&[1; 1024]).expect("write");
+    let free = vfat.borrow_mut().statfs().expect("statfs").free_clusters;
+    assert!(free > 0);
+    vfat.borrow_mut().recount_free_clusters().expect("recount");
+    assert_eq!(vfat.borrow_mut().statfs().expect("statfs").free_clusters, free);
+    // a count lower than the number of clusters preallocated is stale too
+    let disk = formatted_disk!(DISK_SIZE);
+    disk.0.lock().unwrap()[fsinfo + 488..fsinfo + 492].copy_from_slice(&[1, 0, 0, 0]);
+    let vfat = &VFat::from(disk).expect("mount");
+    let mut file = vfat.create_file("/data.bin").expect("create file");
+    file.preallocate(8 * 512).expect("preallocate");
+    let free = vfat.borrow_mut().statfs().expect("statfs").free_clusters;
+    vfat.borrow_mut().recount_free_clusters().expect("recount");
+    assert_eq!(vfat.borrow_mut().statfs().expect("statfs").free_clusters, free);
+}
@@ -89,4 +89,4 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
 impl_for_read_write_seek!(<'a> ::std::io::Cursor<&'a mut [u8]>);
 impl_for_read_write_seek!(::std::io::Cursor<Vec<u8>>);
 impl_for_read_write_seek!(::std::io::Cursor<Box<[u8]>>);
-#[cfg(test)] impl_for_read_write_seek!(::std::fs::File);
+#[cfg(not(target_os = "ros"))] impl_for_read_write_seek!(::std::fs::File);
@@ -21,6 +21,7 @@ impl io::Seek for Dummy {
 impl File for Dummy {
     fn sync(&mut self) -> io::Result<()> { panic!("Dummy") }
     fn size(&self) -> u64 { panic!("Dummy") }
+    fn set_len(&mut self, _size: u64) -> io::Result<()> { panic!("Dummy") }
 }
 
 /// Trait implemented by directories in a file system.
@@ -64,8 +65,10 @@ impl Metadata for Dummy {
     type Timestamp = Dummy;
     fn read_only(&self) -> bool { panic!("Dummy") }
     fn hidden(&self) -> bool { panic!("Dummy") }
+    fn system(&self) -> bool { panic!("Dummy") }
+    fn archive(&self) -> bool { panic!("Dummy") }
     fn created(&self) -> Self::Timestamp { panic!("Dummy") }
     fn accessed(&self) -> Self::Timestamp { panic!("Dummy") }
     fn modified(&self) -> Self::Timestamp { panic!("Dummy") }
-    fn size(&self) -> u32 { panic!("Dummy") }
+    fn size(&self) -> u64 { panic!("Dummy") }
 }
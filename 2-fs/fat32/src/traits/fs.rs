@@ -10,6 +10,11 @@ pub trait File: io::Read + io::Write + io::Seek + Sized {
 
     /// Returns the size of the file in bytes.
     fn size(&self) -> u64;
+
+    /// Truncates or extends the file to `size` bytes. Bytes added to the end
+    /// of the file read as zeroes. If the current offset is past the new end
+    /// of the file, it is moved to the end.
+    fn set_len(&mut self, size: u64) -> io::Result<()>;
 }
 
 /// Trait implemented by directories in a file system.
@@ -179,8 +184,11 @@ pub trait FileSystem: Sized {
     ///
     /// If there is no entry at `path`, an error kind of `NotFound` is returned.
     ///
-    /// If the entry at `path` is a directory and `children` is `false`, an
-    /// error kind of `Other` is returned.
+    /// If the entry at `path` is a non-empty directory and `children` is
+    /// `false`, an error kind of `Other` is returned.
+    ///
+    /// If the entry at `path` or an entry being recursively removed is read
+    /// only, an error kind of `PermissionDenied` is returned.
     ///
     /// All other error values are implementation defined.
     fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()>;
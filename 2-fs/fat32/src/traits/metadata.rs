@@ -34,6 +34,12 @@ pub trait Metadata: Sized {
     /// Whether the entry should be "hidden" from directory traversals.
     fn hidden(&self) -> bool;
 
+    /// Whether the entry belongs to the operating system.
+    fn system(&self) -> bool;
+
+    /// Whether the entry has changed since it was last backed up.
+    fn archive(&self) -> bool;
+
     /// The timestamp when the entry was created.
     fn created(&self) -> Self::Timestamp;
 
@@ -43,6 +49,7 @@ pub trait Metadata: Sized {
     /// The timestamp for the entry's last modification.
     fn modified(&self) -> Self::Timestamp;
 
-    fn size(&self) -> u32;
+    /// The size of the entry's data in bytes.
+    fn size(&self) -> u64;
 }
 
@@ -2,8 +2,10 @@ mod fs;
 mod block_device;
 mod metadata;
 mod dummy;
+mod walk;
 
 pub use self::fs::{Dir, Entry, File, FileSystem};
 pub use self::metadata::{Metadata, Timestamp};
 pub use self::block_device::BlockDevice;
 pub use self::dummy::Dummy;
+pub use self::walk::{walk, Walk, Order};
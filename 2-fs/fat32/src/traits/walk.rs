@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use traits::{Dir, Entry, FileSystem, Metadata};
+
+/// The order in which `Walk` visits entries.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Order {
+    /// Every entry in a directory is visited right after the directory itself.
+    DepthFirst,
+    /// Every entry at one depth is visited before any entry deeper down.
+    BreadthFirst,
+}
+
+/// An iterator over the entries below a directory, created by `walk()`.
+///
+/// Each item is the path of an entry and the entry. The paths are the root
+/// path passed to `walk()` joined with the names of the entries leading to the
+/// entry. The root directory itself is not yielded; its entries are at depth
+/// 1. Within a directory, entries are yielded in the order the directory
+/// lists them.
+///
+/// If listing a directory fails, the error is yielded right after the
+/// directory in place of its entries, and the walk continues with the next
+/// directory.
+pub struct Walk<E: Entry> {
+    /// Directories whose entries are being yielded, with their paths and the
+    /// depth of their entries. Depth-first walks descend by pushing onto this
+    /// stack; breadth-first walks only use its first element.
+    stack: Vec<(PathBuf, usize, <E::Dir as Dir>::Iter)>,
+    /// Directories to yield the entries of once `stack` is empty.
+    queue: VecDeque<(PathBuf, usize, <E::Dir as Dir>::Iter)>,
+    /// The error from listing the directory that was just yielded.
+    pending: Option<io::Error>,
+    order: Order,
+    max_depth: Option<usize>,
+    skip_hidden: bool,
+    skip_dots: bool,
+    predicate: Option<Box<FnMut(&Path, &E) -> bool>>,
+}
+
+/// Returns an iterator over every entry below the directory at `root` in
+/// `fs`, in depth-first order. By default, `.` and `..` entries are skipped
+/// and hidden entries are included; see the methods of `Walk` for the other
+/// options.
+///
+/// # Errors
+///
+/// Returns the errors of `FileSystem::open_dir()` if `root` can't be opened
+/// and the errors of `Dir::entries()` if it can't be listed.
+pub fn walk<F, P>(fs: &F, root: P) -> io::Result<Walk<F::Entry>>
+    where F: FileSystem, P: AsRef<Path>
+{
+    let root = root.as_ref();
+    let entries = fs.open_dir(root)?.entries()?;
+    Ok(Walk {
+        stack: vec![(root.to_path_buf(), 1, entries)],
+        queue: VecDeque::new(),
+        pending: None,
+        order: Order::DepthFirst,
+        max_depth: None,
+        skip_hidden: false,
+        skip_dots: true,
+        predicate: None,
+    })
+}
+
+fn is_dot<E: Entry>(entry: &E) -> bool {
+    entry.name() == "." || entry.name() == ".."
+}
+
+impl<E: Entry> Walk<E> {
+    /// Sets the order in which entries are visited.
+    pub fn order(mut self, order: Order) -> Walk<E> {
+        self.order = order;
+        self
+    }
+
+    /// Stops the walk from descending more than `depth` levels below the root.
+    /// A depth of 1 yields only the entries of the root directory.
+    pub fn max_depth(mut self, depth: usize) -> Walk<E> {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Sets whether hidden entries are skipped. The entries of a skipped
+    /// directory are not visited.
+    pub fn skip_hidden(mut self, skip: bool) -> Walk<E> {
+        self.skip_hidden = skip;
+        self
+    }
+
+    /// Sets whether `.` and `..` entries are skipped. They are never descended
+    /// into.
+    pub fn skip_dots(mut self, skip: bool) -> Walk<E> {
+        self.skip_dots = skip;
+        self
+    }
+
+    /// Only yields the entries for which `predicate`, called with the path of
+    /// an entry and the entry, returns `true`. Unlike skipped entries,
+    /// directories that don't match are still descended into.
+    pub fn matching<P>(mut self, predicate: P) -> Walk<E>
+        where P: FnMut(&Path, &E) -> bool + 'static
+    {
+        self.predicate = Some(Box::new(predicate));
+        self
+    }
+}
+
+impl<E: Entry> Iterator for Walk<E> where E::Dir: Dir<Entry = E> {
+    type Item = io::Result<(PathBuf, E)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(e) = self.pending.take() {
+                return Some(Err(e));
+            }
+
+            let (path, depth, entry) = match self.stack.last_mut() {
+                Some(&mut (ref path, depth, ref mut entries)) => match entries.next() {
+                    Some(entry) => (path.join(entry.name()), depth, entry),
+                    None => {
+                        self.stack.pop();
+                        continue;
+                    }
+                },
+                None => match self.queue.pop_front() {
+                    Some(dir) => {
+                        self.stack.push(dir);
+                        continue;
+                    }
+                    None => return None
+                }
+            };
+
+            if (self.skip_dots && is_dot(&entry)) ||
+                (self.skip_hidden && entry.metadata().hidden()) {
+                continue;
+            }
+
+            let descend = !is_dot(&entry) && self.max_depth.map_or(true, |max| depth < max);
+            if let (true, Some(dir)) = (descend, entry.as_dir()) {
+                match dir.entries() {
+                    Ok(entries) => {
+                        let child = (path.clone(), depth + 1, entries);
+                        match self.order {
+                            Order::DepthFirst => self.stack.push(child),
+                            Order::BreadthFirst => self.queue.push_back(child),
+                        }
+                    }
+                    Err(e) => self.pending = Some(e)
+                }
+            }
+
+            let matches = match self.predicate {
+                Some(ref mut predicate) => predicate(&path, &entry),
+                None => true
+            };
+            if matches {
+                return Some(Ok((path, entry)));
+            }
+        }
+    }
+}
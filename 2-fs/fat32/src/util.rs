@@ -117,3 +117,22 @@ pub fn from_le(bytes: &[u8]) -> u32 {
     ((bytes[2] as u32) << 16) |
     ((bytes[3] as u32) << 24)
 }
+
+pub fn to_le(value: u32) -> [u8; 4] {
+    [value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]
+}
+
+/// Computes the CRC-32 (IEEE 802.3) checksum of `bytes`, as used by GPT.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                0 => crc >> 1,
+                _ => (crc >> 1) ^ 0xEDB88320
+            };
+        }
+    }
+    !crc
+}
@@ -1,12 +1,28 @@
 use std::{cmp, io, fmt};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use traits::BlockDevice;
 
+/// The number of sectors cached by a `CachedDevice` created with `new()`.
+pub const DEFAULT_CAPACITY: usize = 512;
+
 #[derive(Debug)]
 struct CacheEntry {
     data: Vec<u8>,
-    dirty: bool
+    dirty: bool,
+    /// The value of the cache's access counter when this entry was last used.
+    last_used: u64
+}
+
+/// Counters describing how well a `CachedDevice` is performing.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The number of accesses to sectors that were already cached.
+    pub hits: u64,
+    /// The number of accesses that required reading a sector from the disk.
+    pub misses: u64,
+    /// The number of sectors removed from the cache to make room for others.
+    pub evictions: u64,
 }
 
 pub struct Partition {
@@ -19,7 +35,15 @@ pub struct Partition {
 pub struct CachedDevice {
     device: Box<BlockDevice>,
     cache: HashMap<u64, CacheEntry>,
-    partition: Partition
+    partition: Partition,
+    capacity: usize,
+    /// Incremented on every access; used to order the cached sectors by when
+    /// they were last used.
+    accesses: u64,
+    /// The cached sectors keyed by the `last_used` value of their entries, so
+    /// that the first is the least recently used.
+    recency: BTreeMap<u64, u64>,
+    stats: CacheStats
 }
 
 impl CachedDevice {
@@ -39,21 +63,66 @@ impl CachedDevice {
     /// `partition.sector_size` must be an integer multiple of
     /// `device.sector_size()`.
     ///
+    /// At most `DEFAULT_CAPACITY` sectors are cached at once.
+    ///
     /// # Panics
     ///
     /// Panics if the partition's sector size is < the device's sector size.
     pub fn new<T>(device: T, partition: Partition) -> CachedDevice
         where T: BlockDevice + 'static
+    {
+        CachedDevice::with_capacity(device, partition, DEFAULT_CAPACITY)
+    }
+
+    /// Creates a new `CachedDevice` exactly like `new()` that caches at most
+    /// `capacity` sectors at once. When the cache is full, the least recently
+    /// used sector is evicted, and written back first if it is dirty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the partition's sector size is < the device's sector size or
+    /// if `capacity` is 0.
+    pub fn with_capacity<T>(device: T, partition: Partition, capacity: usize) -> CachedDevice
+        where T: BlockDevice + 'static
     {
         assert!(partition.sector_size >= device.sector_size());
+        assert!(capacity > 0);
 
         CachedDevice {
             device: Box::new(device),
             cache: HashMap::new(),
-            partition: partition
+            partition: partition,
+            capacity,
+            accesses: 0,
+            recency: BTreeMap::new(),
+            stats: CacheStats::default()
         }
     }
 
+    /// Returns the hit, miss and eviction counts since the cache was created.
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Changes the maximum number of cached sectors to `capacity`, evicting
+    /// sectors if more than `capacity` are currently cached.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing back an evicted dirty sector fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn set_capacity(&mut self, capacity: usize) -> io::Result<()> {
+        assert!(capacity > 0);
+        self.capacity = capacity;
+        while self.cache.len() > self.capacity {
+            self.evict()?;
+        }
+        Ok(())
+    }
+
     /// Maps a user's request for a sector `virt` to the physical sector and
     /// number of physical sectors required to access `virt`.
     fn virtual_to_physical(&self, virt: u64) -> (u64, u64) {
@@ -94,7 +163,10 @@ impl CachedDevice {
     ///
     /// Returns an error if there is an error reading the sector from the disk.
     pub fn get_mut(&mut self, sector: u64) -> io::Result<&mut [u8]> {
-        unimplemented!("CachedDevice::get_mut()")
+        self.load(sector)?;
+        let entry = self.cache.get_mut(&sector).unwrap();
+        entry.dirty = true;
+        Ok(&mut entry.data)
     }
 
     /// Returns a reference to the cached sector `sector`. If the sector is not
@@ -104,29 +176,101 @@ impl CachedDevice {
     ///
     /// Returns an error if there is an error reading the sector from the disk.
     pub fn get(&mut self, sector: u64) -> io::Result<&[u8]> {
-        let update_cache = match self.cache.get(&sector) {
-            Some(CacheEntry { data: _, dirty: false }) => false,
-            _ => true
+        self.load(sector)?;
+        Ok(&self.cache.get(&sector).unwrap().data)
+    }
+
+    /// Writes every dirty cached sector back to the underlying device and
+    /// marks it clean. Sectors remain cached after being written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing any sector to the disk fails. Sectors that
+    /// were not written remain dirty.
+    pub fn sync(&mut self) -> io::Result<()> {
+        let mut dirty: Vec<u64> = self.cache.iter()
+            .filter(|&(_, entry)| entry.dirty)
+            .map(|(&sector, _)| sector)
+            .collect();
+        dirty.sort();
+
+        for virt in dirty {
+            self.write_back(virt)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the cached sector `virt` to the disk and marks it clean.
+    fn write_back(&mut self, virt: u64) -> io::Result<()> {
+        let (physical_sector, num_sectors) = self.virtual_to_physical(virt);
+        let sector_size = self.device.sector_size();
+        let entry = self.cache.get_mut(&virt).unwrap();
+        for i in 0..num_sectors {
+            let start = (sector_size * i) as usize;
+            self.device.write_sector(
+                physical_sector + i,
+                &entry.data[start..start + sector_size as usize])?;
+        }
+        entry.dirty = false;
+        Ok(())
+    }
+
+    /// Removes the least recently used sector from the cache, writing it back
+    /// to the disk first if it is dirty.
+    fn evict(&mut self) -> io::Result<()> {
+        let (last_used, lru) = match self.recency.iter().next() {
+            Some((&last_used, &sector)) => (last_used, sector),
+            None => return Ok(())
         };
 
-        if update_cache {
-            let data = self.read(sector)?;
-            self.cache.insert(sector, CacheEntry::new(data));
+        if self.cache[&lru].dirty {
+            self.write_back(lru)?;
         }
+        self.cache.remove(&lru);
+        self.recency.remove(&last_used);
+        self.stats.evictions += 1;
+        Ok(())
+    }
 
-        Ok(&self.cache.get(&sector).unwrap().data)
+    /// Reads sector `sector` into the cache if it is not already cached,
+    /// evicting another sector if the cache is full, and marks it as the most
+    /// recently used sector. Cached sectors, clean or dirty, are never reread
+    /// from the disk.
+    fn load(&mut self, sector: u64) -> io::Result<()> {
+        self.accesses += 1;
+        if let Some(entry) = self.cache.get_mut(&sector) {
+            self.recency.remove(&entry.last_used);
+            self.recency.insert(self.accesses, sector);
+            entry.last_used = self.accesses;
+            self.stats.hits += 1;
+            return Ok(());
+        }
+
+        self.stats.misses += 1;
+        while self.cache.len() >= self.capacity {
+            self.evict()?;
+        }
+
+        let data = self.read(sector)?;
+        self.cache.insert(sector, CacheEntry::new(data, self.accesses));
+        self.recency.insert(self.accesses, sector);
+        Ok(())
     }
 }
 
 impl BlockDevice for CachedDevice {
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
-        let amount_to_read = cmp::min(self.partition.sector_size as usize, buf.len());
-        buf.copy_from_slice(&(self.get(n)?)[..amount_to_read]);
+        let sector = self.get(n)?;
+        let amount_to_read = cmp::min(sector.len(), buf.len());
+        buf[..amount_to_read].copy_from_slice(&sector[..amount_to_read]);
         Ok(amount_to_read)
     }
 
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
-        unimplemented!()
+        let sector = self.get_mut(n)?;
+        let amount_to_write = cmp::min(sector.len(), buf.len());
+        sector[..amount_to_write].copy_from_slice(&buf[..amount_to_write]);
+        Ok(amount_to_write)
     }
 }
 
@@ -135,12 +279,14 @@ impl fmt::Debug for CachedDevice {
         f.debug_struct("CachedDevice")
             .field("device", &"<block device>")
             .field("cache", &self.cache)
+            .field("capacity", &self.capacity)
+            .field("stats", &self.stats)
             .finish()
     }
 }
 
 impl CacheEntry {
-    pub fn new(data: Vec<u8>) -> CacheEntry {
-        CacheEntry { data, dirty: false }
+    pub fn new(data: Vec<u8>, last_used: u64) -> CacheEntry {
+        CacheEntry { data, dirty: false, last_used }
     }
 }
@@ -0,0 +1,410 @@
+use std::char::decode_utf16;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{fmt, io};
+
+use vfat::{VFat, Cluster, Status};
+use vfat::vfat::EOC;
+use vfat::dir::{self, BYTES_IN_ENTRY, ENTRY_DELETED, ENTRY_END};
+use vfat::dir::{VFatLfnDirEntry, VFatRegularDirEntry};
+
+/// An inconsistency found by `VFat::check()` or `VFat::repair()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// `cluster` is allocated in the FAT but is not part of any entry's chain.
+    LostCluster(u32),
+    /// `cluster` is part of the chains of both `first` and `second`.
+    CrossLinked { cluster: u32, first: PathBuf, second: PathBuf },
+    /// The chain of `path` reaches `cluster`, which is free, bad, reserved or
+    /// outside of the data region.
+    BrokenChain { path: PathBuf, cluster: u32 },
+    /// The file at `path` is `size` bytes long, but its chain is `clusters`
+    /// clusters long.
+    SizeMismatch { path: PathBuf, size: u32, clusters: u32 },
+    /// The LFN entries starting at entry `index` of the directory `dir` don't
+    /// match the checksum of the regular entry that follows them, or aren't
+    /// followed by a regular entry at all.
+    BadLfnChecksum { dir: PathBuf, index: usize },
+    /// The entry for `cluster` in copy `fat` of the FAT differs from the entry
+    /// in the first FAT.
+    FatMismatch { fat: u8, cluster: u32 },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Problem::*;
+
+        match *self {
+            LostCluster(cluster) => {
+                write!(f, "cluster {} is allocated but unused", cluster)
+            }
+            CrossLinked { cluster, ref first, ref second } => {
+                write!(f, "{} shares cluster {} with {}",
+                       second.display(), cluster, first.display())
+            }
+            BrokenChain { ref path, cluster } => {
+                write!(f, "cluster chain of {} is broken at cluster {}",
+                       path.display(), cluster)
+            }
+            SizeMismatch { ref path, size, clusters } => {
+                write!(f, "{} is {} bytes long but has {} clusters",
+                       path.display(), size, clusters)
+            }
+            BadLfnChecksum { ref dir, index } => {
+                write!(f, "long file name at entry {} of {} is invalid",
+                       index, dir.display())
+            }
+            FatMismatch { fat, cluster } => {
+                write!(f, "FAT {} differs from FAT 0 at cluster {}", fat, cluster)
+            }
+        }
+    }
+}
+
+/// The position of a regular directory entry: the first cluster of its
+/// directory and its index in that directory.
+type Slot = (Cluster, usize);
+
+struct Checker<'a> {
+    vfat: &'a mut VFat,
+    repair: bool,
+    /// The path of the entry each reachable cluster belongs to.
+    owners: HashMap<u32, PathBuf>,
+    problems: Vec<Problem>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(vfat: &'a mut VFat, repair: bool) -> Checker<'a> {
+        Checker { vfat, repair, owners: HashMap::new(), problems: Vec::new() }
+    }
+
+    fn run(mut self) -> io::Result<Vec<Problem>> {
+        for (fat, cluster) in self.vfat.compare_fats(self.repair)? {
+            self.problems.push(Problem::FatMismatch { fat, cluster: cluster.0 });
+        }
+
+        let mut dirs = vec![(self.vfat.root_cluster(), PathBuf::from("/"), None)];
+        while let Some((start, path, slot)) = dirs.pop() {
+            let mut children = self.check_dir(start, &path, slot)?;
+            children.reverse();
+            dirs.extend(children);
+        }
+
+        for raw in 2..self.vfat.num_clusters() + 2 {
+            match self.vfat.fat_entry(Cluster(raw))?.status() {
+                Status::Free | Status::Bad => continue,
+                _ if self.owners.contains_key(&raw) => continue,
+                _ => {}
+            }
+
+            self.problems.push(Problem::LostCluster(raw));
+            if self.repair {
+                self.vfat.set_fat_entry(Cluster(raw), Status::Free)?;
+            }
+        }
+
+        if self.repair {
+            // chains were changed without rewriting the entries that start them
+            self.vfat.dentries.clear();
+            self.vfat.recount_free_clusters()?;
+            self.vfat.sync()?;
+        }
+        Ok(self.problems)
+    }
+
+    /// Checks the chain and entries of the directory at `path` starting at
+    /// `start` and the chains of the files in it. Returns the subdirectories
+    /// that remain to be checked.
+    fn check_dir(
+        &mut self,
+        start: Cluster,
+        path: &Path,
+        slot: Option<Slot>
+    ) -> io::Result<Vec<(Cluster, PathBuf, Option<Slot>)>> {
+        let mut data = Vec::new();
+        match self.vfat.dir_cluster(start) {
+            // the FAT12/16 root directory is a fixed region, not a chain
+            None if slot.is_none() => {
+                self.vfat.read_dir(start, &mut data)?;
+            }
+            _ => {
+                let chain = self.walk_chain(start, path, slot)?;
+                let mut buf = vec![0; self.vfat.cluster_size()];
+                for &cluster in chain.iter() {
+                    self.vfat.read_cluster(cluster, 0, &mut buf)?;
+                    data.extend_from_slice(&buf);
+                }
+            }
+        }
+
+        let mut subdirs = Vec::new();
+        let mut lfns: Vec<(usize, VFatLfnDirEntry)> = Vec::new();
+        for (index, bytes) in data.chunks(BYTES_IN_ENTRY).enumerate() {
+            let mut raw = [0; BYTES_IN_ENTRY];
+            raw.copy_from_slice(bytes);
+
+            if raw[0] == ENTRY_END || raw[0] == ENTRY_DELETED {
+                self.check_lfns(start, path, &lfns, None)?;
+                lfns.clear();
+                if raw[0] == ENTRY_END {
+                    break;
+                }
+                continue;
+            }
+
+            if raw[11] == 0x0F {
+                lfns.push((index, VFatLfnDirEntry::from_bytes(raw)));
+                continue;
+            }
+
+            let entry = VFatRegularDirEntry::from_bytes(raw);
+            let name = match self.check_lfns(start, path, &lfns, Some(&entry))? {
+                Some(name) => name,
+                None => entry.display_name()
+            };
+            lfns.clear();
+
+            if entry.attributes.volume_id() || entry.filename[0] == b'.' {
+                continue;
+            }
+
+            let child = path.join(name);
+            if entry.attributes.directory() {
+                subdirs.push((entry.cluster(), child, Some((start, index))));
+            } else {
+                self.check_file(&entry, &child, (start, index))?;
+            }
+        }
+        Ok(subdirs)
+    }
+
+    /// Checks that the LFN entries `lfns` of the directory `dir` at `path`
+    /// belong to the regular entry `entry`. Returns the long file name they
+    /// store if they are valid, and marks them as deleted when repairing if
+    /// they are not.
+    fn check_lfns(
+        &mut self,
+        dir: Cluster,
+        path: &Path,
+        lfns: &[(usize, VFatLfnDirEntry)],
+        entry: Option<&VFatRegularDirEntry>
+    ) -> io::Result<Option<String>> {
+        if lfns.is_empty() {
+            return Ok(None);
+        }
+
+        if let Some(entry) = entry {
+            let checksum = dir::lfn_checksum(&entry.short_name());
+            if lfns.iter().all(|&(_, ref lfn)| lfn.checksum() == checksum) {
+                let chars: Vec<u16> = lfns.iter().rev()
+                    .flat_map(|&(_, ref lfn)| lfn.chars())
+                    .take_while(|&c| c != 0)
+                    .collect();
+                let name = decode_utf16(chars.into_iter())
+                    .map(|r| r.unwrap_or('_'))
+                    .collect();
+                return Ok(Some(name));
+            }
+        }
+
+        self.problems.push(Problem::BadLfnChecksum {
+            dir: path.to_path_buf(),
+            index: lfns[0].0
+        });
+        if self.repair {
+            for &(index, _) in lfns {
+                let mut raw = self.vfat.read_dir_entry(dir, index)?;
+                raw[0] = ENTRY_DELETED;
+                self.vfat.write_dir_entry(dir, index, &raw)?;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Checks the chain of the file at `path` with regular entry `entry` and
+    /// that its length matches the file's size.
+    fn check_file(
+        &mut self,
+        entry: &VFatRegularDirEntry,
+        path: &Path,
+        slot: Slot
+    ) -> io::Result<()> {
+        let chain = match entry.cluster() {
+            Cluster(0) => Vec::new(),
+            start => self.walk_chain(start, path, Some(slot))?
+        };
+
+        let cluster_size = self.vfat.cluster_size() as u64;
+        let size = entry.size;
+        let expected = ((size as u64 + cluster_size - 1) / cluster_size) as usize;
+        if chain.len() == expected {
+            return Ok(());
+        }
+
+        self.problems.push(Problem::SizeMismatch {
+            path: path.to_path_buf(),
+            size,
+            clusters: chain.len() as u32
+        });
+        if !self.repair {
+            return Ok(());
+        }
+
+        if chain.len() > expected {
+            for cluster in &chain[expected..] {
+                self.owners.remove(&cluster.0);
+                self.vfat.set_fat_entry(*cluster, Status::Free)?;
+            }
+            match expected {
+                0 => self.rewrite_entry(slot, |entry| entry.set_cluster(Cluster(0))),
+                n => self.vfat.set_fat_entry(chain[n - 1], EOC)
+            }
+        } else {
+            let size = (chain.len() as u64 * cluster_size) as u32;
+            self.rewrite_entry(slot, |entry| entry.size = size)
+        }
+    }
+
+    /// Follows the chain starting at `start` that belongs to `path`, claiming
+    /// each cluster for `path`, and returns the clusters in the chain. Stops at
+    /// the first invalid or already claimed cluster, truncating the chain
+    /// there when repairing. A chain that ends in a free cluster is terminated
+    /// at that cluster instead.
+    fn walk_chain(
+        &mut self,
+        start: Cluster,
+        path: &Path,
+        slot: Option<Slot>
+    ) -> io::Result<Vec<Cluster>> {
+        let mut chain = Vec::new();
+        let mut cluster = start;
+        loop {
+            if cluster.0 < 2 || cluster.0 >= self.vfat.num_clusters() + 2 {
+                self.problems.push(Problem::BrokenChain {
+                    path: path.to_path_buf(),
+                    cluster: cluster.0
+                });
+                self.truncate(&chain, slot)?;
+                break;
+            }
+
+            if let Some(owner) = self.owners.get(&cluster.0).cloned() {
+                self.problems.push(Problem::CrossLinked {
+                    cluster: cluster.0,
+                    first: owner,
+                    second: path.to_path_buf()
+                });
+                self.truncate(&chain, slot)?;
+                break;
+            }
+
+            let status = self.vfat.fat_entry(cluster)?.status();
+            if let Status::Bad = status {
+                self.problems.push(Problem::BrokenChain {
+                    path: path.to_path_buf(),
+                    cluster: cluster.0
+                });
+                self.truncate(&chain, slot)?;
+                break;
+            }
+
+            self.owners.insert(cluster.0, path.to_path_buf());
+            chain.push(cluster);
+            match status {
+                Status::Data(next) => cluster = next,
+                Status::Eoc(_) => break,
+                _ => {
+                    self.problems.push(Problem::BrokenChain {
+                        path: path.to_path_buf(),
+                        cluster: cluster.0
+                    });
+                    if self.repair {
+                        self.vfat.set_fat_entry(cluster, EOC)?;
+                    }
+                    break;
+                }
+            }
+        }
+        Ok(chain)
+    }
+
+    /// When repairing, ends the chain after its last valid cluster `chain`. If
+    /// there is none, the file at `slot` is emptied and the directory at `slot`
+    /// is deleted, since a directory starting at cluster 0 is the root.
+    fn truncate(&mut self, chain: &[Cluster], slot: Option<Slot>) -> io::Result<()> {
+        if !self.repair {
+            return Ok(());
+        }
+
+        let slot = match (chain.last(), slot) {
+            (Some(&last), _) => return self.vfat.set_fat_entry(last, EOC),
+            (None, Some(slot)) => slot,
+            (None, None) => return Ok(())
+        };
+
+        let (dir, index) = slot;
+        let entry = VFatRegularDirEntry::from_bytes(self.vfat.read_dir_entry(dir, index)?);
+        if !entry.attributes.directory() {
+            return self.rewrite_entry(slot, |entry| {
+                entry.set_cluster(Cluster(0));
+                entry.size = 0;
+            });
+        }
+
+        let mut start = index;
+        while start > 0 {
+            let raw = self.vfat.read_dir_entry(dir, start - 1)?;
+            if raw[11] != 0x0F || raw[0] == ENTRY_DELETED {
+                break;
+            }
+            start -= 1;
+        }
+        for index in start..index + 1 {
+            let mut raw = self.vfat.read_dir_entry(dir, index)?;
+            raw[0] = ENTRY_DELETED;
+            self.vfat.write_dir_entry(dir, index, &raw)?;
+        }
+        Ok(())
+    }
+
+    /// Applies `f` to the regular directory entry at `slot`.
+    fn rewrite_entry<F>(&mut self, slot: Slot, f: F) -> io::Result<()>
+        where F: FnOnce(&mut VFatRegularDirEntry)
+    {
+        let (dir, index) = slot;
+        let mut entry = VFatRegularDirEntry::from_bytes(self.vfat.read_dir_entry(dir, index)?);
+        f(&mut entry);
+        self.vfat.write_dir_entry(dir, index, &entry.to_bytes())
+    }
+}
+
+impl VFat {
+    /// Checks the consistency of the file system without modifying it and
+    /// returns every problem found.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading from the disk fails.
+    pub fn check(&mut self) -> io::Result<Vec<Problem>> {
+        Checker::new(self, false).run()
+    }
+
+    /// Checks the consistency of the file system like `check()`, fixing each
+    /// problem found, and writes the changes to the disk. Returns the problems
+    /// that were found.
+    ///
+    /// Mismatched FAT copies are rebuilt from the first FAT. Invalid and
+    /// cross-linked chains are truncated before the offending cluster, and
+    /// chains ending in a free cluster end there. Directories left without any
+    /// clusters are deleted. Files are truncated to the
+    /// length of their chain, and chains longer than their file are freed past
+    /// its end. Invalid long file names are deleted, leaving the short name.
+    /// Lost clusters are freed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading from or writing to the disk fails.
+    pub fn repair(&mut self) -> io::Result<Vec<Problem>> {
+        Checker::new(self, true).run()
+    }
+}
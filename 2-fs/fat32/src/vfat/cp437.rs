@@ -0,0 +1,29 @@
+/// The characters of bytes 0x80 to 0xFF in code page 437, the OEM code page
+/// that short names and volume labels are usually stored in.
+const HIGH_HALF: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+/// Returns the character that `byte` stands for in code page 437. Bytes below
+/// 0x80 are ASCII.
+pub(crate) fn decode(byte: u8) -> char {
+    match byte {
+        0...0x7F => byte as char,
+        _ => HIGH_HALF[byte as usize - 0x80]
+    }
+}
+
+/// Returns the code page 437 byte for `c`, or `None` if there is none.
+pub(crate) fn encode(c: char) -> Option<u8> {
+    match c as u32 {
+        0...0x7F => Some(c as u8),
+        _ => HIGH_HALF.iter().position(|&high| high == c).map(|i| 0x80 + i as u8)
+    }
+}
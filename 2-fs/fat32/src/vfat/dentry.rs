@@ -0,0 +1,117 @@
+use std::collections::{BTreeMap, HashMap};
+
+use vfat::{Cluster, Metadata, CacheStats};
+use vfat::dir::EntryLocation;
+
+/// The number of entries kept in a `DentryCache` created with `new()`.
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// The decoded directory entries of an entry, without the file system handle
+/// that `Entry` holds.
+#[derive(Debug, Clone)]
+pub(crate) struct CachedEntry {
+    pub metadata: Metadata,
+    pub start_cluster: Cluster,
+    pub location: EntryLocation,
+}
+
+#[derive(Debug)]
+struct Slot {
+    entry: CachedEntry,
+    /// The value of the cache's access counter when this entry was last used.
+    last_used: u64
+}
+
+/// A cache of the results of looking up names in directories, keyed by the
+/// first cluster of the directory and the name as compared by `Dir::find`.
+/// When the cache is full, the least recently used entry is evicted.
+///
+/// Entries are not kept up to date: every entry in a directory must be
+/// invalidated whenever one of the directory's entries is written.
+#[derive(Debug)]
+pub(crate) struct DentryCache {
+    entries: HashMap<(Cluster, String), Slot>,
+    capacity: usize,
+    /// Incremented on every lookup and insertion; used to order the entries by
+    /// when they were last used.
+    accesses: u64,
+    /// The keys of the entries keyed by their `last_used` values, so that the
+    /// first is the least recently used.
+    recency: BTreeMap<u64, (Cluster, String)>,
+    stats: CacheStats
+}
+
+impl DentryCache {
+    /// Creates an empty cache holding at most `DEFAULT_CAPACITY` entries.
+    pub fn new() -> DentryCache {
+        DentryCache {
+            entries: HashMap::new(),
+            capacity: DEFAULT_CAPACITY,
+            accesses: 0,
+            recency: BTreeMap::new(),
+            stats: CacheStats::default()
+        }
+    }
+
+    /// Returns the hit, miss and eviction counts since the cache was created.
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Returns the cached entry named `key` in the directory starting at
+    /// `dir`, and marks it as the most recently used entry.
+    pub fn get(&mut self, dir: Cluster, key: &str) -> Option<CachedEntry> {
+        self.accesses += 1;
+        let key = (dir, key.to_string());
+        match self.entries.get_mut(&key) {
+            Some(slot) => {
+                self.recency.remove(&slot.last_used);
+                self.recency.insert(self.accesses, key.clone());
+                slot.last_used = self.accesses;
+                self.stats.hits += 1;
+                Some(slot.entry.clone())
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Caches `entry` as the entry named `key` in the directory starting at
+    /// `dir`, evicting the least recently used entry if the cache is full.
+    pub fn insert(&mut self, dir: Cluster, key: String, entry: CachedEntry) {
+        self.accesses += 1;
+        let key = (dir, key);
+        if let Some(old) = self.entries.remove(&key) {
+            self.recency.remove(&old.last_used);
+        } else if self.entries.len() >= self.capacity {
+            let lru = self.recency.keys().next().cloned();
+            if let Some(lru) = lru.and_then(|last_used| self.recency.remove(&last_used)) {
+                self.entries.remove(&lru);
+                self.stats.evictions += 1;
+            }
+        }
+        self.recency.insert(self.accesses, key.clone());
+        self.entries.insert(key, Slot { entry, last_used: self.accesses });
+    }
+
+    /// Removes every cached entry in the directory starting at `dir`.
+    pub fn invalidate(&mut self, dir: Cluster) {
+        let keys: Vec<(Cluster, String)> = self.entries.keys()
+            .filter(|&&(cached_dir, _)| cached_dir == dir)
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(slot) = self.entries.remove(&key) {
+                self.recency.remove(&slot.last_used);
+            }
+        }
+    }
+
+    /// Removes every cached entry.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
@@ -1,19 +1,63 @@
 use std::ffi::OsStr;
-use std::char::decode_utf16;
+use std::char::{decode_utf16, REPLACEMENT_CHARACTER};
 use std::borrow::Cow;
 use std::{cmp, fmt, io, mem};
 
 use traits;
 use util::VecExt;
-use vfat::{VFat, Shared, File, Cluster, Entry};
+use vfat::{VFat, Shared, File, Cluster, Entry, CachedEntry};
 use vfat::{Metadata, Attributes, Timestamp, Time, Date};
+use vfat::cp437;
 
-const BYTES_IN_ENTRY: usize = 32;
+pub(crate) const BYTES_IN_ENTRY: usize = 32;
+
+/// The first byte of a directory entry that is unused.
+pub(crate) const ENTRY_DELETED: u8 = 0xE5;
+
+/// The first byte of the directory entry that ends a directory.
+pub(crate) const ENTRY_END: u8 = 0x00;
+
+/// The number of UTF-16 code units stored in a single LFN entry.
+const LFN_CHARS_PER_ENTRY: usize = 13;
+
+/// The maximum number of UTF-16 code units in a long file name.
+const MAX_LFN_LEN: usize = 255;
+
+/// The maximum number of entries in a directory.
+const MAX_DIR_ENTRIES: usize = 65536;
+
+/// Characters other than upper case letters and digits allowed in short names.
+const SHORT_NAME_SPECIAL: &[u8] = b"!#$%&'()-@^_`{}~";
+
+/// Case flags of a regular entry: its base name or extension is displayed in
+/// lower case. Set by Windows NT and later instead of writing LFN entries.
+const CASE_LOWER_BASE: u8 = 0x08;
+const CASE_LOWER_EXT: u8 = 0x10;
+
+/// The byte stored in place of a short name's first byte when it is 0xE5,
+/// which would otherwise mark the entry as deleted.
+const ESCAPED_DELETED: u8 = 0x05;
+
+/// Attributes that describe what an entry is rather than how it may be used.
+const FIXED_ATTRIBUTES: Attributes = Attributes(0x18);
 
 pub struct Dir {
     pub metadata: Metadata,
     pub start_cluster: Cluster,
-    pub vfat: Shared<VFat>
+    pub vfat: Shared<VFat>,
+    pub(crate) location: Option<EntryLocation>
+}
+
+/// The position of an entry's on-disk directory entries in its parent
+/// directory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct EntryLocation {
+    /// The first cluster of the parent directory.
+    pub dir: Cluster,
+    /// The index of the first directory entry (LFN or regular) of the entry.
+    pub start: usize,
+    /// The index of the regular directory entry of the entry.
+    pub regular: usize,
 }
 
 #[repr(C, packed)]
@@ -22,12 +66,16 @@ pub struct VFatRegularDirEntry {
     pub filename: [u8; 8],
     pub extension: [u8; 3],
     pub attributes: Attributes,
-    pub _reserved: u8,
+    /// `CASE_LOWER_BASE` and `CASE_LOWER_EXT`
+    pub case_flags: u8,
+    /// hundredths of a second past `created_time`, in range [0, 200)
     pub created_cs: u8,
-    pub created: Timestamp,
+    pub created_time: Time,
+    pub created_date: Date,
     pub accessed: Date,
     pub cluster_hi: u16,
-    pub last_modified: Timestamp,
+    pub modified_time: Time,
+    pub modified_date: Date,
     pub cluster_lo: u16,
     pub size: u32,
 }
@@ -51,6 +99,390 @@ pub struct VFatUnknownDirEntry {
     pub _bytes: [u8; 32]
 }
 
+impl VFatRegularDirEntry {
+    /// The first cluster of the entry's data.
+    pub fn cluster(&self) -> Cluster {
+        Cluster::from(((self.cluster_hi as u32) << 16) | (self.cluster_lo as u32))
+    }
+
+    pub fn set_cluster(&mut self, cluster: Cluster) {
+        self.cluster_hi = (cluster.0 >> 16) as u16;
+        self.cluster_lo = cluster.0 as u16;
+    }
+
+    /// The time the entry was created.
+    pub fn created(&self) -> Timestamp {
+        Timestamp {
+            date: self.created_date,
+            time: self.created_time,
+            centiseconds: self.created_cs
+        }
+    }
+
+    pub fn set_created(&mut self, created: Timestamp) {
+        self.created_date = created.date;
+        self.created_time = created.time;
+        self.created_cs = created.centiseconds;
+    }
+
+    /// The time the entry was last modified.
+    pub fn last_modified(&self) -> Timestamp {
+        Timestamp { date: self.modified_date, time: self.modified_time, centiseconds: 0 }
+    }
+
+    pub fn set_last_modified(&mut self, modified: Timestamp) {
+        self.modified_date = modified.date;
+        self.modified_time = modified.time;
+    }
+
+    pub fn from_bytes(bytes: [u8; BYTES_IN_ENTRY]) -> VFatRegularDirEntry {
+        unsafe { mem::transmute(bytes) }
+    }
+
+    pub fn to_bytes(self) -> [u8; BYTES_IN_ENTRY] {
+        unsafe { mem::transmute(self) }
+    }
+}
+
+impl VFatRegularDirEntry {
+    /// Returns a directory entry with short name `short_name` that describes
+    /// an entry with metadata `metadata` whose data starts at `cluster`.
+    fn new(short_name: &[u8; 11], metadata: &Metadata, cluster: Cluster) -> VFatRegularDirEntry {
+        let mut entry = VFatRegularDirEntry {
+            filename: [0; 8],
+            extension: [0; 3],
+            attributes: metadata.attributes,
+            case_flags: 0,
+            created_cs: metadata.created.centiseconds,
+            created_time: metadata.created.time,
+            created_date: metadata.created.date,
+            accessed: metadata.accessed,
+            cluster_hi: 0,
+            modified_time: metadata.last_modified.time,
+            modified_date: metadata.last_modified.date,
+            cluster_lo: 0,
+            size: metadata.size,
+        };
+        entry.set_short_name(short_name);
+        entry.set_cluster(cluster);
+        entry
+    }
+
+    pub fn set_short_name(&mut self, short_name: &[u8; 11]) {
+        self.filename.copy_from_slice(&short_name[..8]);
+        self.extension.copy_from_slice(&short_name[8..]);
+    }
+
+    /// The 8.3 name of the entry, as stored on disk.
+    pub fn short_name(&self) -> [u8; 11] {
+        let mut short_name = [0; 11];
+        short_name[..8].copy_from_slice(&self.filename);
+        short_name[8..].copy_from_slice(&self.extension);
+        short_name
+    }
+
+    /// The 8.3 name of the entry as displayed, e.g. `README.TXT`, decoded
+    /// from code page 437 and in lower case where the case flags say so.
+    pub(crate) fn display_name(&self) -> String {
+        let mut filename = self.filename;
+        if filename[0] == ESCAPED_DELETED {
+            filename[0] = ENTRY_DELETED;
+        }
+
+        let mut name = decode_short_name(&filename);
+        if self.case_flags & CASE_LOWER_BASE != 0 {
+            name = name.to_lowercase();
+        }
+        let mut extension = decode_short_name(&self.extension);
+        if self.case_flags & CASE_LOWER_EXT != 0 {
+            extension = extension.to_lowercase();
+        }
+        if !extension.is_empty() {
+            name.push_str(".");
+            name.push_str(&extension);
+        }
+        name
+    }
+}
+
+impl VFatLfnDirEntry {
+    /// Returns the LFN entries that store the long file name `name` for the
+    /// regular entry with short name `short_name`, in on-disk order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `name` is too long.
+    fn for_name(name: &str, short_name: &[u8; 11]) -> io::Result<Vec<VFatLfnDirEntry>> {
+        let mut chars: Vec<u16> = name.encode_utf16().collect();
+        if chars.len() > MAX_LFN_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "name too long"));
+        }
+        if chars.len() % LFN_CHARS_PER_ENTRY != 0 {
+            chars.push(0);
+        }
+        while chars.len() % LFN_CHARS_PER_ENTRY != 0 {
+            chars.push(0xFFFF);
+        }
+
+        let checksum = lfn_checksum(short_name);
+        let num_entries = chars.len() / LFN_CHARS_PER_ENTRY;
+        let mut entries = Vec::new();
+        for (i, part) in chars.chunks(LFN_CHARS_PER_ENTRY).enumerate().rev() {
+            let mut bytes = [0u8; 2 * LFN_CHARS_PER_ENTRY];
+            for (j, c) in part.iter().enumerate() {
+                bytes[2 * j] = *c as u8;
+                bytes[2 * j + 1] = (*c >> 8) as u8;
+            }
+
+            let last = if i + 1 == num_entries { 0x40 } else { 0 };
+            let mut entry = VFatLfnDirEntry {
+                seq_no: (i + 1) as u8 | last,
+                chars1: [0; 10],
+                attributes: Attributes::LFN,
+                dirtype: 0,
+                checksum,
+                chars2: [0; 12],
+                _r: [0; 2],
+                chars3: [0; 4]
+            };
+            entry.chars1.copy_from_slice(&bytes[..10]);
+            entry.chars2.copy_from_slice(&bytes[10..22]);
+            entry.chars3.copy_from_slice(&bytes[22..]);
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    fn to_bytes(self) -> [u8; BYTES_IN_ENTRY] {
+        unsafe { mem::transmute(self) }
+    }
+
+    pub(crate) fn from_bytes(bytes: [u8; BYTES_IN_ENTRY]) -> VFatLfnDirEntry {
+        unsafe { mem::transmute(bytes) }
+    }
+
+    /// The checksum of the short name of the regular entry this entry belongs
+    /// to.
+    pub(crate) fn checksum(&self) -> u8 {
+        self.checksum
+    }
+
+    /// The UTF-16 code units of the name stored in this entry, including any
+    /// terminator and padding.
+    pub(crate) fn chars(&self) -> Vec<u16> {
+        let mut bytes = Vec::with_capacity(2 * LFN_CHARS_PER_ENTRY);
+        bytes.extend_from_slice(&self.chars1);
+        bytes.extend_from_slice(&self.chars2);
+        bytes.extend_from_slice(&self.chars3);
+        bytes.chunks(2).map(|c| c[0] as u16 | (c[1] as u16) << 8).collect()
+    }
+}
+
+/// Computes the checksum of a short name stored in each of its LFN entries.
+pub(crate) fn lfn_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, &b| {
+        ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(b)
+    })
+}
+
+/// Decodes part of a short name from code page 437, stopping at the padding.
+fn decode_short_name(part: &[u8]) -> String {
+    part.iter()
+        .take_while(|&&b| b != 0x00 && b != b' ')
+        .map(|&b| cp437::decode(b))
+        .collect()
+}
+
+/// Whether the code page 437 byte `c` may appear in a short name. Lower case
+/// ASCII letters may not.
+fn is_short_name_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || c >= 0x80 ||
+        SHORT_NAME_SPECIAL.contains(&c)
+}
+
+/// Converts `c` to the code page 437 byte that stands for it in a short name,
+/// converting it to upper case first. Returns `None` if `c` can't appear in a
+/// short name.
+fn short_name_byte(c: char) -> Option<u8> {
+    match cp437::encode(single_char(c.to_uppercase()).unwrap_or(c)) {
+        Some(byte) if is_short_name_char(byte) => Some(byte),
+        _ => None
+    }
+}
+
+/// If `name` is a valid ASCII 8.3 name whose base name and extension are each
+/// entirely upper or entirely lower case, returns its on-disk form and the
+/// case flags that restore its case. Otherwise returns `None`.
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.rfind('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, "")
+    };
+
+    let valid = |part: &str, max: usize| {
+        part.len() <= max && part.bytes().all(|c| is_short_name_char(c.to_ascii_uppercase()))
+    };
+    if !name.is_ascii() || base.is_empty() || !valid(base, 8) || !valid(ext, 3) ||
+        (ext.is_empty() && name.ends_with('.')) {
+        return None;
+    }
+
+    let case_flag = |part: &str, flag: u8| {
+        let lower = part.bytes().any(|c| c.is_ascii_lowercase());
+        let upper = part.bytes().any(|c| c.is_ascii_uppercase());
+        match (lower, upper) {
+            (true, true) => None,
+            (true, false) => Some(flag),
+            _ => Some(0)
+        }
+    };
+    let case_flags = case_flag(base, CASE_LOWER_BASE)? | case_flag(ext, CASE_LOWER_EXT)?;
+
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.to_ascii_uppercase().as_bytes());
+    short_name[8..8 + ext.len()].copy_from_slice(ext.to_ascii_uppercase().as_bytes());
+    Some((short_name, case_flags))
+}
+
+/// Generates a short name for the long file name `name` that is not in
+/// `existing` by converting `name` into an upper case 8.3 name in code page
+/// 437 and replacing the end of its base with the first free numeric tail
+/// `~1` to `~999999`. Characters that can't appear in short names become `_`.
+fn generate_short_name(name: &str, existing: &[[u8; 11]]) -> io::Result<[u8; 11]> {
+    fn convert(part: &str, max: usize) -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| short_name_byte(c).unwrap_or(b'_'))
+            .take(max)
+            .collect()
+    }
+
+    let name = name.trim_left_matches(|c| c == '.' || c == ' ');
+    let (base, ext) = match name.rfind('.') {
+        Some(i) => (convert(&name[..i], 8), convert(&name[i + 1..], 3)),
+        None => (convert(name, 8), Vec::new())
+    };
+
+    for n in 1..1000000 {
+        let tail = format!("~{}", n);
+        let keep = cmp::min(base.len(), 8 - tail.len());
+        let mut short_name = [b' '; 11];
+        short_name[..keep].copy_from_slice(&base[..keep]);
+        short_name[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        short_name[8..8 + ext.len()].copy_from_slice(&ext);
+        if short_name[0] == ENTRY_DELETED {
+            short_name[0] = ESCAPED_DELETED;
+        }
+        if !existing.contains(&short_name) {
+            return Ok(short_name);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "no free short name"))
+}
+
+/// Returns the only character in `chars`, or `None` if there isn't exactly one.
+fn single_char<I: Iterator<Item = char>>(mut chars: I) -> Option<char> {
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None
+    }
+}
+
+/// Maps `c` to the character it is compared as in case-insensitive lookups,
+/// following Unicode simple case folding: characters whose case mapping takes
+/// more than one character, like `ß`, only match themselves.
+fn fold_case(c: char) -> char {
+    let upper = single_char(c.to_uppercase()).unwrap_or(c);
+    single_char(upper.to_lowercase()).unwrap_or(upper)
+}
+
+/// Removes the trailing dots and spaces from `name`, which FAT ignores, unless
+/// `name` is `.` or `..`.
+pub(crate) fn trim_name(name: &str) -> &str {
+    match name {
+        "." | ".." => name,
+        _ => name.trim_right_matches(|c| c == '.' || c == ' ')
+    }
+}
+
+/// Returns the form of `name` that is compared in lookups: case folded and
+/// without trailing dots and spaces. Two names match if their keys are equal.
+pub(crate) fn lookup_key(name: &str) -> String {
+    trim_name(name).chars().map(fold_case).collect()
+}
+
+/// Checks that `name` can be used as the name of a new entry and returns it
+/// without trailing dots and spaces.
+pub(crate) fn validate_name(name: &str) -> io::Result<&str> {
+    let name = trim_name(name);
+    if name.is_empty() || name == "." || name == ".." ||
+        name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c)) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid file name"));
+    }
+    Ok(name)
+}
+
+/// Returns the index of the first of `needed` consecutive free entries in
+/// `entries`. If there is no such run, the returned index is that of the
+/// free run extending to the end of `entries`, or `entries.len()`.
+fn find_free_entries(entries: &[[u8; BYTES_IN_ENTRY]], needed: usize) -> usize {
+    let mut run = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        match entry[0] {
+            ENTRY_END => return i - run,
+            ENTRY_DELETED => {
+                run += 1;
+                if run == needed {
+                    return i + 1 - run;
+                }
+            }
+            _ => run = 0
+        }
+    }
+    entries.len() - run
+}
+
+/// Replaces the attributes of the entry at `location` with `attributes`,
+/// rewriting its regular directory entry and updating `metadata` to match.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if the entry is the root directory or if
+/// `attributes` would change whether the entry is a directory or volume label.
+pub(crate) fn set_attributes(
+    vfat: &Shared<VFat>,
+    location: Option<EntryLocation>,
+    metadata: &mut Metadata,
+    attributes: Attributes
+) -> io::Result<()> {
+    let location = location.ok_or(
+        io::Error::new(io::ErrorKind::InvalidInput, "root directory has no attributes"))?;
+    if attributes & FIXED_ATTRIBUTES != metadata.attributes & FIXED_ATTRIBUTES {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "cannot change directory or volume id attributes"));
+    }
+
+    let mut vfat = vfat.borrow_mut();
+    let raw_entry = vfat.read_dir_entry(location.dir, location.regular)?;
+    let mut entry = VFatRegularDirEntry::from_bytes(raw_entry);
+    entry.attributes = attributes;
+    vfat.write_dir_entry(location.dir, location.regular, &entry.to_bytes())?;
+    metadata.attributes = attributes;
+    Ok(())
+}
+
+/// Reads all of the raw directory entries in the directory starting at
+/// `start`.
+fn read_raw_entries(vfat: &mut VFat, start: Cluster) -> io::Result<Vec<[u8; BYTES_IN_ENTRY]>> {
+    let mut buf: Vec<u8> = Vec::new();
+    vfat.read_dir(start, &mut buf)?;
+    Ok(buf.chunks(BYTES_IN_ENTRY).map(|chunk| {
+        let mut entry = [0; BYTES_IN_ENTRY];
+        entry.copy_from_slice(chunk);
+        entry
+    }).collect())
+}
+
 pub union VFatDirEntry {
     unknown: VFatUnknownDirEntry,
     regular: VFatRegularDirEntry,
@@ -59,7 +491,8 @@ pub union VFatDirEntry {
 
 impl Dir {
     /// Finds the entry named `name` in `self` and returns it. Comparison is
-    /// case-insensitive.
+    /// case-insensitive using Unicode simple case folding, and trailing dots
+    /// and spaces are ignored.
     ///
     /// # Errors
     ///
@@ -68,39 +501,393 @@ impl Dir {
     ///
     /// If `name` contains invalid UTF-8 characters, an error of `InvalidInput`
     /// is returned.
+    ///
+    /// If `name` matches the name of an entry only after decoding it lossily
+    /// because its long file name contains an unpaired surrogate, an error of
+    /// `InvalidData` is returned.
     pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry> {
+        let key = match name.as_ref().to_str() {
+            None => { return Err(io::Error::new(io::ErrorKind::InvalidInput, "name not valid utf8")) },
+            Some(name) => lookup_key(name)
+        };
+
+        let (dir, cached) = {
+            let mut vfat = self.vfat.borrow_mut();
+            let dir = vfat.dentry_dir(self.start_cluster);
+            (dir, vfat.dentries.get(dir, &key))
+        };
+        if let Some(cached) = cached {
+            return Ok(self.cached_entry(cached));
+        }
+
         for entry in traits::Dir::entries(self)? {
-            let name = match name.as_ref().to_str() {
-                None => { return Err(io::Error::new(io::ErrorKind::InvalidInput, "name not valid utf8")) },
-                Some(name) => name
-            };
+            if lookup_key(traits::Entry::name(&entry)) == key {
+                let metadata = traits::Entry::metadata(&entry);
+                if metadata.lossy_name {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                              "long file name contains an unpaired surrogate"));
+                }
 
-            if traits::Entry::name(&entry).eq_ignore_ascii_case(name) {
+                if let Some(location) = entry.location() {
+                    let cached = CachedEntry {
+                        metadata: metadata.clone(),
+                        start_cluster: entry.start_cluster(),
+                        location,
+                    };
+                    self.vfat.borrow_mut().dentries.insert(dir, key, cached);
+                }
                 return Ok(entry);
             }
         }
         Err(io::Error::new(io::ErrorKind::NotFound, "Entry not found"))
     }
+
+    /// Rebuilds the entry in `self` described by `cached`.
+    fn cached_entry(&self, cached: CachedEntry) -> Entry {
+        let vfat = self.vfat.clone();
+        match cached.metadata.attributes.directory() {
+            true => Entry::Dir(Dir {
+                metadata: cached.metadata,
+                start_cluster: cached.start_cluster,
+                vfat,
+                location: Some(cached.location),
+            }),
+            false => Entry::File(File::new(
+                cached.metadata,
+                cached.start_cluster,
+                vfat,
+                Some(cached.location),
+            ))
+        }
+    }
+
+    /// Creates a new, empty file named `name` in `self` and returns it.
+    ///
+    /// # Errors
+    ///
+    /// If an entry named `name` already exists, an error of `AlreadyExists` is
+    /// returned.
+    ///
+    /// If `name` is not a valid file name, an error of `InvalidInput` is
+    /// returned.
+    pub fn create_file<P: AsRef<OsStr>>(&self, name: P) -> io::Result<File> {
+        let metadata = self.new_metadata(name.as_ref(), Attributes::ARCHIVE)?;
+        let entry = VFatRegularDirEntry::new(b"           ", &metadata, Cluster(0));
+        let location = self.insert(&metadata.name, entry)?;
+        Ok(File::new(metadata, Cluster(0), self.vfat.clone(), Some(location)))
+    }
+
+    /// Creates a new directory named `name` in `self` and returns it. The new
+    /// directory contains only the `.` and `..` entries.
+    ///
+    /// # Errors
+    ///
+    /// If an entry named `name` already exists, an error of `AlreadyExists` is
+    /// returned.
+    ///
+    /// If `name` is not a valid file name, an error of `InvalidInput` is
+    /// returned.
+    pub fn create_dir<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Dir> {
+        let metadata = self.new_metadata(name.as_ref(), Attributes::DIRECTORY)?;
+        let start_cluster = self.vfat.borrow_mut().alloc_cluster(None)?;
+        let dir = Dir { metadata, start_cluster, vfat: self.vfat.clone(), location: None };
+
+        let init = || -> io::Result<EntryLocation> {
+            {
+                let mut vfat = self.vfat.borrow_mut();
+                let zeroes = vec![0; vfat.cluster_size()];
+                vfat.write_cluster(start_cluster, 0, &zeroes)?;
+
+                let dot = VFatRegularDirEntry::new(b".          ", &dir.metadata, start_cluster);
+                let dotdot = VFatRegularDirEntry::new(b"..         ", &dir.metadata, Cluster(0));
+                vfat.write_dir_entry(start_cluster, 0, &dot.to_bytes())?;
+                vfat.write_dir_entry(start_cluster, 1, &dotdot.to_bytes())?;
+            }
+            dir.set_parent(self)?;
+
+            let entry = VFatRegularDirEntry::new(b"           ", &dir.metadata, start_cluster);
+            self.insert(&dir.metadata.name, entry)
+        };
+
+        match init() {
+            Ok(location) => Ok(Dir { location: Some(location), ..dir }),
+            Err(e) => {
+                // nothing points at the new cluster, so it would be lost
+                self.vfat.borrow_mut().free_chain(start_cluster)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Replaces the attributes of `self` on disk. The directory attribute
+    /// cannot be changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `self` is the root directory or
+    /// if `attributes` does not have the directory attribute set.
+    pub fn set_attributes(&mut self, attributes: Attributes) -> io::Result<()> {
+        set_attributes(&self.vfat, self.location, &mut self.metadata, attributes)
+    }
+
+    /// Points the `..` entry of `self` at the directory `parent`.
+    pub(crate) fn set_parent(&self, parent: &Dir) -> io::Result<()> {
+        let mut vfat = self.vfat.borrow_mut();
+
+        // `..` refers to the root directory as cluster 0
+        let parent_cluster = if parent.start_cluster == vfat.root_cluster() {
+            Cluster(0)
+        } else {
+            parent.start_cluster
+        };
+
+        let raw_entry = vfat.read_dir_entry(self.start_cluster, 1)?;
+        let mut dotdot = VFatRegularDirEntry::from_bytes(raw_entry);
+        dotdot.set_cluster(parent_cluster);
+        vfat.write_dir_entry(self.start_cluster, 1, &dotdot.to_bytes())
+    }
+
+    /// Returns metadata, timestamped with the current time, for a new entry in
+    /// `self` named `name` with attributes `attributes`.
+    fn new_metadata(&self, name: &OsStr, attributes: Attributes) -> io::Result<Metadata> {
+        let name = match name.to_str() {
+            None => { return Err(io::Error::new(io::ErrorKind::InvalidInput, "name not valid utf8")) },
+            Some(name) => name
+        };
+        let name = validate_name(name)?;
+
+        match self.find(name) {
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, "entry exists")),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e)
+        }
+
+        let now = self.vfat.borrow().now();
+        Ok(Metadata {
+            name: name.to_string(),
+            size: 0,
+            attributes,
+            lossy_name: false,
+            created: now,
+            accessed: now.date,
+            // modification times are stored to a precision of 2 seconds
+            last_modified: Timestamp { centiseconds: 0, ..now },
+        })
+    }
+
+    /// Writes the LFN directory entries for `name` followed by `entry` into
+    /// free entries of `self`, growing the directory if there are not enough
+    /// free entries. The short name of `entry` is replaced with one derived
+    /// from `name`. Returns the location of the new entry.
+    pub(crate) fn insert(
+        &self,
+        name: &str,
+        mut entry: VFatRegularDirEntry
+    ) -> io::Result<EntryLocation> {
+        let mut vfat = self.vfat.borrow_mut();
+        let entries = read_raw_entries(&mut vfat, self.start_cluster)?;
+        let end = entries.iter().position(|e| e[0] == ENTRY_END).unwrap_or(entries.len());
+        let existing: Vec<[u8; 11]> = entries[..end].iter()
+            .map(|&e| VFatRegularDirEntry::from_bytes(e))
+            .filter(|e| e.filename[0] != ENTRY_DELETED && !e.attributes.is_lfn())
+            .map(|e| e.short_name())
+            .collect();
+
+        let (short_name, case_flags, lfn_entries) = match exact_short_name(name) {
+            Some((short_name, case_flags)) if !existing.contains(&short_name) => {
+                (short_name, case_flags, Vec::new())
+            }
+            _ => {
+                let short_name = generate_short_name(name, &existing)?;
+                (short_name, 0, VFatLfnDirEntry::for_name(name, &short_name)?)
+            }
+        };
+
+        let needed = lfn_entries.len() + 1;
+        let start = reserve_entries(&mut vfat, self.start_cluster, &entries, needed)?;
+        for (i, lfn_entry) in lfn_entries.into_iter().enumerate() {
+            vfat.write_dir_entry(self.start_cluster, start + i, &lfn_entry.to_bytes())?;
+        }
+        let regular = start + needed - 1;
+        entry.set_short_name(&short_name);
+        entry.case_flags = case_flags;
+        vfat.write_dir_entry(self.start_cluster, regular, &entry.to_bytes())?;
+        keep_end_marker(&mut vfat, self.start_cluster, &entries, end, regular)?;
+
+        Ok(EntryLocation { dir: self.start_cluster, start, regular })
+    }
+}
+
+/// Returns the index of the first of `needed` consecutive free entries in the
+/// directory starting at `dir`, whose raw entries are `entries`. The directory
+/// is grown if there are not enough free entries.
+///
+/// # Errors
+///
+/// Returns an error of `Other` if the directory can't hold `needed` more
+/// entries or the disk is full.
+fn reserve_entries(
+    vfat: &mut VFat,
+    dir: Cluster,
+    entries: &[[u8; BYTES_IN_ENTRY]],
+    needed: usize
+) -> io::Result<usize> {
+    let start = find_free_entries(entries, needed);
+    if start + needed > MAX_DIR_ENTRIES {
+        return Err(io::Error::new(io::ErrorKind::Other, "directory is full"));
+    }
+
+    let entries_per_cluster = vfat.cluster_size() / BYTES_IN_ENTRY;
+    let mut capacity = entries.len();
+    if capacity < start + needed {
+        // the FAT12/16 root directory region can't grow
+        let mut last = match vfat.dir_cluster(dir) {
+            Some(cluster) => vfat.last_cluster(cluster)?,
+            None => return Err(io::Error::new(io::ErrorKind::Other, "directory is full"))
+        };
+
+        let zeroes = vec![0; vfat.cluster_size()];
+        while capacity < start + needed {
+            last = vfat.alloc_cluster(Some(last))?;
+            vfat.write_cluster(last, 0, &zeroes)?;
+            capacity += entries_per_cluster;
+        }
+    }
+    Ok(start)
+}
+
+/// Makes sure the directory starting at `dir` still ends after the entry at
+/// `index`, which was just written. `entries` are the directory's raw entries
+/// before the write and `end` is the index of its end marker.
+fn keep_end_marker(
+    vfat: &mut VFat,
+    dir: Cluster,
+    entries: &[[u8; BYTES_IN_ENTRY]],
+    end: usize,
+    index: usize
+) -> io::Result<()> {
+    // entries past the end marker may hold garbage; if the end marker was
+    // overwritten, the next entry must become the end marker
+    let next = index + 1;
+    if next > end && next < entries.len() && entries[next][0] != ENTRY_END {
+        vfat.write_dir_entry(dir, next, &[0; BYTES_IN_ENTRY])?;
+    }
+    Ok(())
+}
+
+/// Converts `label` to its on-disk form as a volume label in code page 437,
+/// converting lower case letters to upper case. Returns `None` if `label` is
+/// empty.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `label` is longer than 11 characters,
+/// starts with a space or contains characters not allowed in short names.
+pub(crate) fn volume_label(label: &str) -> io::Result<Option<[u8; 11]>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "invalid volume label");
+    let label = label.trim_right_matches(' ');
+    if label.is_empty() {
+        return Ok(None);
+    } else if label.starts_with(' ') || label.chars().count() > 11 {
+        return Err(invalid());
+    }
+
+    let mut bytes = [b' '; 11];
+    for (i, c) in label.chars().enumerate() {
+        bytes[i] = match c {
+            ' ' => b' ',
+            c => short_name_byte(c).ok_or_else(invalid)?
+        };
+    }
+    Ok(Some(bytes))
+}
+
+/// Decodes the on-disk form of a volume label from code page 437, removing
+/// the padding. A first byte of 0x05 stands for 0xE5, as in short names.
+pub(crate) fn decode_label(label: &[u8; 11]) -> String {
+    let mut label = *label;
+    if label[0] == ESCAPED_DELETED {
+        label[0] = ENTRY_DELETED;
+    }
+    label.iter().map(|&b| cp437::decode(b)).collect::<String>()
+        .trim_right_matches(|c| c == ' ' || c == '\0')
+        .to_string()
+}
+
+/// Returns the index and contents of the volume label entry in the root
+/// directory, if there is one.
+pub(crate) fn find_volume_entry(vfat: &mut VFat) -> io::Result<Option<(usize, VFatRegularDirEntry)>> {
+    let root = vfat.root_cluster();
+    Ok(read_raw_entries(vfat, root)?.into_iter()
+        .take_while(|e| e[0] != ENTRY_END)
+        .map(VFatRegularDirEntry::from_bytes)
+        .enumerate()
+        .find(|&(_, e)| e.filename[0] != ENTRY_DELETED && e.attributes.volume_id()))
+}
+
+/// Writes a volume label entry for `label` to the root directory, replacing
+/// the existing one. If `label` is `None`, the existing entry is deleted.
+pub(crate) fn write_volume_entry(vfat: &mut VFat, label: Option<&[u8; 11]>) -> io::Result<()> {
+    let root = vfat.root_cluster();
+    let (index, mut entry) = match (find_volume_entry(vfat)?, label) {
+        (Some(existing), _) => existing,
+        (None, None) => return Ok(()),
+        (None, Some(_)) => {
+            let entries = read_raw_entries(vfat, root)?;
+            let end = entries.iter().position(|e| e[0] == ENTRY_END).unwrap_or(entries.len());
+            let index = reserve_entries(vfat, root, &entries, 1)?;
+            keep_end_marker(vfat, root, &entries, end, index)?;
+            let mut entry = VFatRegularDirEntry::from_bytes([0; BYTES_IN_ENTRY]);
+            entry.attributes = Attributes::VOLUME_ID;
+            (index, entry)
+        }
+    };
+
+    match label {
+        Some(label) => {
+            entry.set_short_name(label);
+            // a label starting with 0xE5 would mark the entry as deleted
+            if entry.filename[0] == ENTRY_DELETED {
+                entry.filename[0] = ESCAPED_DELETED;
+            }
+            let now = vfat.now();
+            entry.set_last_modified(Timestamp { centiseconds: 0, ..now });
+        }
+        None => entry.filename[0] = ENTRY_DELETED
+    }
+    vfat.write_dir_entry(root, index, &entry.to_bytes())
 }
 
 pub struct DirIter {
     vfat: Shared<VFat>,
     dir_entries: Vec<VFatDirEntry>,
+    dir: Cluster,
+    /// The number of directory entries popped from `dir_entries` so far.
+    index: usize,
 }
 
 impl DirIter {
     fn new(dir: &Dir) -> io::Result<DirIter> {
         let mut vfat = dir.vfat.borrow_mut();
-        let mut dir_entries: Vec<VFatDirEntry> = Vec::new();
-        let mut buf: Vec<u8> = Vec::new();
-        let mut static_buf = [0; BYTES_IN_ENTRY];
-        vfat.read_chain(dir.start_cluster, &mut buf)?;
-        for entry in buf.chunks(BYTES_IN_ENTRY) {
-            static_buf.copy_from_slice(entry);
-            unsafe { dir_entries.push(mem::transmute(static_buf)); }
-        }
+        let mut dir_entries: Vec<VFatDirEntry> = read_raw_entries(&mut vfat, dir.start_cluster)?
+            .into_iter()
+            .map(|entry| unsafe { mem::transmute(entry) })
+            .collect();
         dir_entries.reverse();
-        Ok(DirIter { vfat: dir.vfat.clone(), dir_entries })
+        Ok(DirIter {
+            vfat: dir.vfat.clone(),
+            dir_entries,
+            dir: dir.start_cluster,
+            index: 0
+        })
+    }
+
+    fn pop(&mut self) -> Option<VFatDirEntry> {
+        let entry = self.dir_entries.pop();
+        if entry.is_some() {
+            self.index += 1;
+        }
+        entry
     }
 }
 
@@ -108,52 +895,68 @@ impl Iterator for DirIter {
     type Item = Entry;
 
     fn next(&mut self) -> Option<Entry> {
-        if self.dir_entries.is_empty() {
-            return None;
-        }
-
-        let mut next = self.dir_entries.pop().unwrap();
-        let mut unknown = unsafe { next.unknown };
-        while unknown._bytes[0] == 0 || unknown._bytes[0] == 0x0E5 {
-            if unknown._bytes[0] == 0x0E5 {
-                next = match self.dir_entries.pop() {
-                    Some(val) => val,
-                    None => { return None; }
-                };
-                unknown = unsafe { next.unknown };
-            } else {
-                return None;
+        let (start, is_lfn, mut name_bytes, next) = loop {
+            let mut next = match self.pop() {
+                Some(entry) => entry,
+                None => return None
+            };
+            let mut unknown = unsafe { next.unknown };
+            while unknown._bytes[0] == 0 || unknown._bytes[0] == 0x0E5 {
+                if unknown._bytes[0] == 0x0E5 {
+                    next = match self.pop() {
+                        Some(val) => val,
+                        None => { return None; }
+                    };
+                    unknown = unsafe { next.unknown };
+                } else {
+                    return None;
+                }
             }
-        }
 
-        let mut name = String::new();
-        let mut name_bytes = Vec::new();
-        let mut is_lfn = false;
+            let start = self.index - 1;
+            let mut name_bytes = Vec::new();
+            let mut is_lfn = false;
 
-        while unknown._bytes[11] == 0xF {
-            let lfn = unsafe { next.long_filename };
+            while unknown._bytes[11] == 0xF {
+                let lfn = unsafe { next.long_filename };
 
-            if lfn.seq_no != 0xE5 {
-                is_lfn = true;
-                let mut tmp_buf = Vec::new();
-                tmp_buf.extend_from_slice(&lfn.chars1);
-                tmp_buf.extend_from_slice(&lfn.chars2);
-                tmp_buf.extend_from_slice(&lfn.chars3);
+                if lfn.seq_no != 0xE5 {
+                    is_lfn = true;
+                    let mut tmp_buf = Vec::new();
+                    tmp_buf.extend_from_slice(&lfn.chars1);
+                    tmp_buf.extend_from_slice(&lfn.chars2);
+                    tmp_buf.extend_from_slice(&lfn.chars3);
 
-                tmp_buf.reverse();
-                name_bytes.extend_from_slice(&tmp_buf);
+                    tmp_buf.reverse();
+                    name_bytes.extend_from_slice(&tmp_buf);
+                }
+
+                // a directory may end part way through a long file name
+                next = match self.pop() {
+                    Some(entry) => entry,
+                    None => return None
+                };
+                unknown = unsafe { next.unknown };
             }
 
-            next = self.dir_entries.pop().unwrap();
-            unknown = unsafe { next.unknown };
-        }
+            // LFN entries must be followed by the regular entry they belong to
+            match unknown._bytes[0] {
+                ENTRY_END => return None,
+                ENTRY_DELETED => continue,
+                // the volume label is found by `VFat::label()`, not listed
+                _ if unsafe { next.regular }.attributes.volume_id() => continue,
+                _ => break (start, is_lfn, name_bytes, next)
+            }
+        };
 
+        let mut name = String::new();
+        let mut lossy_name = false;
         name_bytes.reverse();
 
         let reg = unsafe { next.regular };
 
         if is_lfn {
-            let mut chars: Vec<u16> = name_bytes
+            let chars: Vec<u16> = name_bytes
                 .iter()
                 .skip(1)
                 .step_by(2)
@@ -161,55 +964,47 @@ impl Iterator for DirIter {
                 .map(|(first, second)| ((*first as u16) << 8) | (*second as u16))
                 .collect();
 
-            let end = match chars.iter().position(|n| *n == 0 || *n == 0xFF) {
+            // the name ends at a NUL, and any slots after it are 0xFFFF padding
+            let mut end = match chars.iter().position(|n| *n == 0) {
                 Some(n) => n,
                 None => chars.len(),
             };
+            while end > 0 && chars[end - 1] == 0xFFFF {
+                end -= 1;
+            }
 
-            name.push_str(
-                &decode_utf16(&mut chars[..end].iter().cloned())
-                    .map(|r| r.unwrap_or('_'))
-                    .collect::<String>(),
-            );
-        } else {
-            let end = match reg.filename.iter().position(|n| *n == 0 || *n == 0x20) {
-                Some(n) => n,
-                None => reg.filename.len(),
-            };
-
-            name.push_str(&String::from_utf8_lossy(&reg.filename[..end]));
-            let end = match reg.extension.iter().position(|b| *b == 0x00 || *b == 0x20) {
-                Some(pos) => pos,
-                None => reg.extension.len()
-            };
-            if end > 0 {
-                name.push_str(".");
-                name.push_str(&String::from_utf8_lossy(&reg.extension[..end]));
+            for c in decode_utf16(chars[..end].iter().cloned()) {
+                lossy_name |= c.is_err();
+                name.push(c.unwrap_or(REPLACEMENT_CHARACTER));
             }
+        } else {
+            name.push_str(&reg.display_name());
         }
 
-        let start_cluster = ((reg.cluster_hi as u32) << 16) | (reg.cluster_lo as u32);
+        let location = EntryLocation { dir: self.dir, start, regular: self.index - 1 };
         let metadata = Metadata {
             name,
             size: reg.size,
             attributes: reg.attributes,
-            created: reg.created,
+            lossy_name,
+            created: reg.created(),
             accessed: reg.accessed,
-            last_modified: reg.last_modified,
+            last_modified: reg.last_modified(),
         };
 
-        // TODO: use constant for attribute masks
-        if reg.attributes.0 & 0x10 != 0 {
+        if reg.attributes.directory() {
             Some(Entry::Dir(Dir {
                 metadata,
-                start_cluster: Cluster::from(start_cluster),
+                start_cluster: reg.cluster(),
                 vfat: self.vfat.clone(),
+                location: Some(location),
             }))
         } else {
             Some(Entry::File(File::new(
                 metadata,
-                Cluster::from(start_cluster),
+                reg.cluster(),
                 self.vfat.clone(),
+                Some(location),
             )))
         }
     }
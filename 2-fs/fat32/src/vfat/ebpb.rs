@@ -65,6 +65,52 @@ impl BiosParameterBlock {
     }
 }
 
+impl BiosParameterBlock {
+    /// Whether the fields of the BPB describe a plausible FAT volume. This
+    /// tells a volume boot sector apart from an MBR, which has the same
+    /// signature.
+    pub fn is_plausible(&self) -> bool {
+        (self.jmp[0] == 0xEB || self.jmp[0] == 0xE9)
+            && self.validate().is_ok()
+            && (self.is_fat32_layout() || self.is_fat16_layout())
+    }
+
+    /// Checks the fields that the layout of the file system is computed from,
+    /// so that a corrupt boot sector can't cause out of range accesses or
+    /// divisions by zero.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidBpb` naming the first invalid field.
+    pub fn validate(&self) -> Result<(), Error> {
+        let bytes_per_sector = self.bytes_per_sector;
+        if !bytes_per_sector.is_power_of_two() || bytes_per_sector < 512 ||
+            bytes_per_sector > 4096 {
+            return Err(Error::InvalidBpb("bytes per sector"));
+        } else if !self.sectors_per_cluster.is_power_of_two() {
+            return Err(Error::InvalidBpb("sectors per cluster"));
+        } else if self.num_reserved == 0 {
+            return Err(Error::InvalidBpb("number of reserved sectors"));
+        } else if self.num_fat == 0 {
+            return Err(Error::InvalidBpb("number of FATs"));
+        } else if self.sectors_per_fat == 0 && self.sectors_per_fat32 == 0 {
+            return Err(Error::InvalidBpb("sectors per FAT"));
+        }
+        Ok(())
+    }
+
+    /// Whether the FAT size and root directory fields have the FAT32 layout.
+    fn is_fat32_layout(&self) -> bool {
+        self.sectors_per_fat == 0 && self.sectors_per_fat32 != 0 && self.max_dirs == 0
+    }
+
+    /// Whether the FAT size and root directory fields have the FAT12/16
+    /// layout, with a fixed-size root directory.
+    fn is_fat16_layout(&self) -> bool {
+        self.sectors_per_fat != 0 && self.max_dirs != 0
+    }
+}
+
 impl fmt::Debug for BiosParameterBlock {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("BiosParameterBlock")
@@ -1,5 +1,8 @@
+use std::io;
+
 use traits;
-use vfat::{File, Dir, Metadata, Cluster};
+use vfat::{File, Dir, Metadata, Cluster, Attributes};
+use vfat::dir::EntryLocation;
 
 // TODO: You may need to change this definition.
 #[derive(Debug)]
@@ -8,7 +11,33 @@ pub enum Entry {
     Dir(Dir)
 }
 
-// TODO: Implement any useful helper methods on `Entry`.
+impl Entry {
+    /// The location of the entry's directory entries in its parent directory
+    /// or `None` if the entry is the root directory.
+    pub(crate) fn location(&self) -> Option<EntryLocation> {
+        match self {
+            Entry::Dir(dir) => dir.location,
+            Entry::File(file) => file.location
+        }
+    }
+
+    /// Replaces the attributes of the entry on disk. See
+    /// `File::set_attributes` and `Dir::set_attributes`.
+    pub fn set_attributes(&mut self, attributes: Attributes) -> io::Result<()> {
+        match self {
+            Entry::Dir(dir) => dir.set_attributes(attributes),
+            Entry::File(file) => file.set_attributes(attributes)
+        }
+    }
+
+    /// The first cluster of the entry's data.
+    pub(crate) fn start_cluster(&self) -> Cluster {
+        match self {
+            Entry::Dir(dir) => dir.start_cluster,
+            Entry::File(file) => file.start_cluster
+        }
+    }
+}
 
 // FIXME: Implement `traits::Entry` for `Entry`.
 impl traits::Entry for Entry {
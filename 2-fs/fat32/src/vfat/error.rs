@@ -1,12 +1,16 @@
 use std::io;
 
+use gpt;
 use mbr;
 
 #[derive(Debug)]
 pub enum Error {
     Mbr(mbr::Error),
+    Gpt(gpt::Error),
     Io(io::Error),
     BadSignature,
+    /// A field of the BIOS parameter block describes an impossible layout.
+    InvalidBpb(&'static str),
     NotFound
 }
 
@@ -16,6 +20,12 @@ impl From<mbr::Error> for Error {
     }
 }
 
+impl From<gpt::Error> for Error {
+    fn from(error: gpt::Error) -> Error {
+        Error::Gpt(error)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Error {
         Error::Io(error)
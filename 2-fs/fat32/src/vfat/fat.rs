@@ -19,6 +19,70 @@ pub enum Status {
     Eoc(u32)
 }
 
+impl Status {
+    /// Returns the 28-bit FAT entry value that encodes `self`.
+    pub fn to_raw(&self) -> u32 {
+        match *self {
+            Free => 0,
+            Reserved => 0x1,
+            Data(cluster) => cluster.0,
+            Bad => 0xFFFFFF7,
+            Eoc(value) => value & 0x0FFFFFFF,
+        }
+    }
+}
+
+/// The width of the entries in a file allocation table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// Returns the FAT type of a volume with `num_clusters` data clusters, as
+    /// specified by Microsoft.
+    pub fn from_cluster_count(num_clusters: u64) -> FatType {
+        match num_clusters {
+            0...4084 => FatType::Fat12,
+            4085...65524 => FatType::Fat16,
+            _ => FatType::Fat32
+        }
+    }
+
+    /// The number of bits in a FAT entry.
+    pub fn bits(&self) -> u32 {
+        match *self {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        }
+    }
+
+    /// The offset, in bytes from the start of the FAT, of the entry for
+    /// `cluster`.
+    pub fn entry_offset(&self, cluster: Cluster) -> u64 {
+        cluster.0 as u64 * self.bits() as u64 / 8
+    }
+
+    /// The number of entries that fit in a FAT of `bytes` bytes.
+    pub fn entries_in(&self, bytes: u64) -> u64 {
+        bytes * 8 / self.bits() as u64
+    }
+
+    /// Extends a raw 12 or 16-bit FAT entry to the equivalent 28-bit FAT32
+    /// entry, so that reserved, bad and end of chain values keep their
+    /// meaning.
+    pub fn widen(&self, raw: u32) -> u32 {
+        match *self {
+            FatType::Fat12 if raw >= 0xFF0 => raw | 0x0FFFF000,
+            FatType::Fat16 if raw >= 0xFFF0 => raw | 0x0FFF0000,
+            _ => raw
+        }
+    }
+}
+
 #[repr(C, packed)]
 pub struct FatEntry(pub u32);
 
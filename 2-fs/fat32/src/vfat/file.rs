@@ -2,7 +2,9 @@ use std::cmp::{max, min};
 use std::io::{self, SeekFrom};
 
 use traits;
-use vfat::{Cluster, Metadata, Shared, VFat};
+use vfat::{Attributes, Cluster, Metadata, Shared, VFat};
+use vfat::vfat::EOC;
+use vfat::dir::{self, EntryLocation, VFatRegularDirEntry};
 
 #[derive(Debug)]
 pub struct File {
@@ -10,35 +12,150 @@ pub struct File {
     pub start_cluster: Cluster,
     pub vfat: Shared<VFat>,
     pub offset: u32,
-    data: Option<Vec<u8>>
+    pub(crate) location: Option<EntryLocation>,
+    /// The most recently accessed cluster and its index in the file's chain.
+    /// Walks of the chain start here when possible.
+    current: Option<(usize, Cluster)>
 }
 
 impl File {
-    pub fn new(metadata: Metadata, start_cluster: Cluster, vfat: Shared<VFat>) -> File {
+    pub(crate) fn new(
+        metadata: Metadata,
+        start_cluster: Cluster,
+        vfat: Shared<VFat>,
+        location: Option<EntryLocation>
+    ) -> File {
         File {
             metadata,
             start_cluster,
             vfat,
             offset: 0u32,
-            data: None,
+            location,
+            current: None,
         }
     }
 
-    pub fn initialize(&mut self) -> io::Result<()> {
-        match self.data {
-            Some(_) => Ok(()),
-            None => {
-                let mut tmp_buf = Vec::new();
-                self.vfat.borrow_mut().read_chain(self.start_cluster, &mut tmp_buf)?;
-                self.data =Some(tmp_buf);
+    /// Replaces the attributes of `self` on disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `attributes` would make `self` a
+    /// directory or volume label.
+    pub fn set_attributes(&mut self, attributes: Attributes) -> io::Result<()> {
+        dir::set_attributes(&self.vfat, self.location, &mut self.metadata, attributes)
+    }
+
+    /// Reserves clusters for the first `size` bytes of the file without
+    /// changing its size, so that writes up to `size` don't allocate. The new
+    /// clusters are allocated as one contiguous run following the file's last
+    /// cluster if possible. Does nothing if the file already has enough
+    /// clusters.
+    ///
+    /// Clusters past the end of the file are freed by `set_len()` and are
+    /// reported as a size mismatch by `VFat::check()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `PermissionDenied` if the file is read only.
+    ///
+    /// Returns an error of `Other` if `size` is larger than the maximum FAT32
+    /// file size of 4GiB - 1 or if the disk doesn't have enough free clusters.
+    pub fn preallocate(&mut self, size: u64) -> io::Result<()> {
+        self.check_len(size)?;
+        let shared = self.vfat.clone();
+        let mut vfat = shared.borrow_mut();
+        let cluster_size = vfat.cluster_size() as u64;
+        let needed = ((size + cluster_size - 1) / cluster_size) as u32;
+
+        let (last, have) = match self.start_cluster {
+            Cluster(0) => (None, 0),
+            start => {
+                let (last, have) = vfat.chain_end(start)?;
+                (Some(last), have)
+            }
+        };
+        if have >= needed {
+            return Ok(());
+        }
+
+        let count = needed - have;
+        match vfat.alloc_contiguous(last, count) {
+            Ok(first) => {
+                if last.is_none() {
+                    self.start_cluster = first;
+                }
                 Ok(())
             }
+            // fall back to whatever clusters are free, linking each into the
+            // file's chain so that none are lost if the disk fills up
+            Err(ref e) if e.kind() == io::ErrorKind::Other => {
+                let mut prev = last;
+                for _ in 0..count {
+                    let cluster = vfat.alloc_cluster(prev)?;
+                    if prev.is_none() {
+                        self.start_cluster = cluster;
+                    }
+                    prev = Some(cluster);
+                }
+                Ok(())
+            }
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Returns an error if the file can't be resized to `size` bytes.
+    fn check_len(&self, size: u64) -> io::Result<()> {
+        if self.metadata.attributes.read_only() {
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, "file is read only"))
+        } else if size > ::std::u32::MAX as u64 {
+            Err(io::Error::new(io::ErrorKind::Other, "file too large"))
+        } else {
+            Ok(())
         }
     }
+
+    /// Returns the `index`th cluster of the file by following the FAT from
+    /// the closest cluster already known. No file data is read.
+    ///
+    /// If the file's cluster chain is shorter than `index + 1` clusters,
+    /// returns `None` unless `allocate` is `true`, in which case free clusters
+    /// are allocated and linked onto the end of the chain until it is long
+    /// enough.
+    fn cluster_at(
+        &mut self,
+        vfat: &mut VFat,
+        index: usize,
+        allocate: bool
+    ) -> io::Result<Option<Cluster>> {
+        if self.start_cluster.0 == 0 {
+            if !allocate {
+                return Ok(None);
+            }
+            self.start_cluster = vfat.alloc_cluster(None)?;
+        }
+
+        let (mut i, mut cluster) = match self.current {
+            Some((i, cluster)) if i <= index => (i, cluster),
+            _ => (0, self.start_cluster)
+        };
+
+        while i < index {
+            cluster = match vfat.next_cluster(cluster)? {
+                Some(next) => next,
+                None if allocate => vfat.alloc_cluster(Some(cluster))?,
+                None => return Ok(None)
+            };
+            i += 1;
+        }
+
+        self.current = Some((index, cluster));
+        Ok(Some(cluster))
+    }
 }
 
 impl io::Seek for File {
-    /// Seek to offset `pos` in the file.
+    /// Seek to offset `pos` in the file. No file data is read; the cluster
+    /// containing the new offset is found on the next read or write.
     ///
     /// A seek to the end of the file is allowed. A seek _beyond_ the end of the
     /// file returns an `InvalidInput` error.
@@ -54,7 +171,7 @@ impl io::Seek for File {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
         let new_offset: i64 = match pos {
             SeekFrom::Start(offset) =>  offset as i64,
-            SeekFrom::End(offset) => self.metadata.size as i64 - offset,
+            SeekFrom::End(offset) => self.metadata.size as i64 + offset,
             SeekFrom::Current(offset) => self.offset as i64 + offset
         };
 
@@ -68,36 +185,155 @@ impl io::Seek for File {
 }
 
 impl traits::File for File {
+    /// Writes the file's size, first cluster and timestamps back to its
+    /// directory entry, then writes all cached changes to the disk.
     fn sync(&mut self) -> io::Result<()> {
-        unimplemented!()
+        let mut vfat = self.vfat.borrow_mut();
+        if let Some(location) = self.location {
+            let raw_entry = vfat.read_dir_entry(location.dir, location.regular)?;
+            let mut entry = VFatRegularDirEntry::from_bytes(raw_entry);
+            entry.set_cluster(self.start_cluster);
+            entry.size = self.metadata.size;
+            entry.set_created(self.metadata.created);
+            entry.accessed = self.metadata.accessed;
+            entry.set_last_modified(self.metadata.last_modified);
+            vfat.write_dir_entry(location.dir, location.regular, &entry.to_bytes())?;
+        }
+        vfat.sync()
     }
 
     fn size(&self) -> u64 {
         self.metadata.size as u64
     }
+
+    /// Shrinking frees the clusters past the new end of the file, including
+    /// clusters reserved by `preallocate()`. Growing zeroes the new bytes,
+    /// allocating clusters as needed.
+    ///
+    /// Like `write()`, the new size is only written to the file's directory
+    /// entry by `sync()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `PermissionDenied` if the file is read only.
+    ///
+    /// Returns an error of `Other` if `size` is larger than the maximum FAT32
+    /// file size of 4GiB - 1 or if the disk is full.
+    fn set_len(&mut self, size: u64) -> io::Result<()> {
+        self.check_len(size)?;
+        let shared = self.vfat.clone();
+        let mut vfat = shared.borrow_mut();
+        let cluster_size = vfat.cluster_size();
+        let (old_size, size) = (self.metadata.size as usize, size as usize);
+        let needed = (size + cluster_size - 1) / cluster_size;
+
+        if size < old_size || needed == 0 {
+            self.current = None;
+            if needed == 0 {
+                vfat.free_chain(self.start_cluster)?;
+                self.start_cluster = Cluster(0);
+            } else if let Some(last) = self.cluster_at(&mut vfat, needed - 1, false)? {
+                if let Some(next) = vfat.next_cluster(last)? {
+                    vfat.set_fat_entry(last, EOC)?;
+                    vfat.free_chain(next)?;
+                }
+            }
+        } else {
+            // clusters past the old end may hold stale or preallocated data
+            let zeroes = vec![0; cluster_size];
+            let mut pos = old_size;
+            while pos < size {
+                let cluster = self.cluster_at(&mut vfat, pos / cluster_size, true)?.unwrap();
+                let offset = pos % cluster_size;
+                let amount = min(cluster_size - offset, size - pos);
+                vfat.write_cluster(cluster, offset, &zeroes[..amount])?;
+                pos += amount;
+            }
+        }
+
+        self.metadata.size = size as u32;
+        self.offset = min(self.offset, self.metadata.size);
+        self.metadata.set_modified(vfat.now());
+        Ok(())
+    }
 }
 
 impl io::Write for File {
+    /// Writes `buf` at the current offset, overwriting existing data and
+    /// growing the file as needed. At most one cluster is written per call.
+    ///
+    /// Changes to the file's size and modification time are only written to
+    /// its directory entry by `sync()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `PermissionDenied` if the file is read only.
+    ///
+    /// Returns an error of `Other` if the disk is full or if the write would
+    /// grow the file past the maximum FAT32 file size of 4GiB - 1.
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        unimplemented!()
+        if self.metadata.attributes.read_only() {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "file is read only"));
+        } else if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let shared = self.vfat.clone();
+        let mut vfat = shared.borrow_mut();
+        let cluster_size = vfat.cluster_size();
+        let max_write = (::std::u32::MAX - self.offset) as usize;
+        if max_write == 0 {
+            return Err(io::Error::new(io::ErrorKind::Other, "file too large"));
+        }
+
+        let offset = self.offset as usize;
+        let cluster = self.cluster_at(&mut vfat, offset / cluster_size, true)?.unwrap();
+        let buf = &buf[..min(buf.len(), max_write)];
+        let written = vfat.write_cluster(cluster, offset % cluster_size, buf)?;
+
+        self.offset += written as u32;
+        self.metadata.size = max(self.metadata.size, self.offset);
+        self.metadata.set_modified(vfat.now());
+        Ok(written)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        unimplemented!()
+        traits::File::sync(self)
     }
 }
 
 impl io::Read for File {
+    /// Reads from the current offset into `buf`, one cluster at a time,
+    /// directly from the sectors of the cluster chain. The file is never read
+    /// into memory as a whole.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the file's cluster chain is shorter
+    /// than its size or is otherwise corrupt.
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self.data.is_none() {
-            self.initialize()?;
+        let num_bytes_to_read = min(buf.len(), (self.metadata.size - self.offset) as usize);
+        if num_bytes_to_read == 0 {
+            return Ok(0);
         }
 
-        let num_bytes_to_read = min(buf.len(), (self.metadata.size - self.offset) as usize);
+        let shared = self.vfat.clone();
+        let mut vfat = shared.borrow_mut();
+        let cluster_size = vfat.cluster_size();
 
-        buf[..num_bytes_to_read].copy_from_slice(
-            &self.data.as_ref().unwrap()[self.offset as usize..self.offset as usize + num_bytes_to_read]);
-        io::Seek::seek(self, SeekFrom::Current(num_bytes_to_read as i64))?;
-        Ok(num_bytes_to_read)
+        let mut bytes_read = 0;
+        while bytes_read < num_bytes_to_read {
+            let offset = self.offset as usize + bytes_read;
+            let cluster = self.cluster_at(&mut vfat, offset / cluster_size, false)?
+                .ok_or(io::Error::new(io::ErrorKind::InvalidData,
+                                      "cluster chain is shorter than the file"))?;
+            bytes_read += vfat.read_cluster(
+                cluster,
+                offset % cluster_size,
+                &mut buf[bytes_read..num_bytes_to_read])?;
+        }
+
+        self.offset += bytes_read as u32;
+        Ok(bytes_read)
     }
-}
\ No newline at end of file
+}
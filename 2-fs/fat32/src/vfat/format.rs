@@ -0,0 +1,234 @@
+use std::{io, mem};
+
+use mbr::{self, MasterBootRecord, PartitionEntry};
+use traits::BlockDevice;
+use util::to_le;
+use vfat::{Attributes, BiosParameterBlock, FatType, FsInfo};
+use vfat::vfat::NO_LABEL;
+
+/// The offset of the first partition from the start of the device, in bytes.
+const PARTITION_OFFSET: u64 = 1 << 20;
+
+const NUM_RESERVED: u16 = 32;
+const NUM_FATS: u8 = 2;
+const FSINFO_SECTOR: u16 = 1;
+const BACKUP_BOOT_SECTOR: u16 = 6;
+const ROOT_CLUSTER: u32 = 2;
+const MEDIA_DESCRIPTOR: u8 = 0xF8;
+/// The largest number of clusters a FAT32 volume can have: entries from
+/// 0x0FFFFFF7 up are reserved.
+const MAX_CLUSTERS: u64 = 0x0FFFFFF5 - 2;
+
+/// The partition type of a FAT32 partition addressed with LBA.
+const PARTITION_TYPE_FAT32_LBA: u8 = 0x0C;
+
+/// Options for `format_with()`.
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    /// The volume label, padded with spaces. Defaults to `NO NAME`.
+    pub volume_label: [u8; 11],
+    /// The volume serial number. Defaults to 0.
+    pub volume_id: u32,
+    /// The number of sectors per cluster, a power of two. If `None`, the
+    /// default, the cluster size is chosen from the size of the volume.
+    pub sectors_per_cluster: Option<u8>,
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions {
+            volume_label: *NO_LABEL,
+            volume_id: 0,
+            sectors_per_cluster: None,
+        }
+    }
+}
+
+/// Formats the first `size` bytes of `device` with the default options. See
+/// `format_with()`.
+pub fn format<T: BlockDevice>(device: T, size: u64) -> io::Result<()> {
+    format_with(device, size, &FormatOptions::default())
+}
+
+/// Formats the first `size` bytes of `device` as a disk with a single FAT32
+/// partition that spans the whole disk after the first MiB. The partition
+/// uses the device's sector size and contains an empty root directory.
+///
+/// Only the MBR, the reserved sectors, the FATs and the root directory cluster
+/// are written.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `size` is too small or too large
+/// for a FAT32 volume, which must have at least 65525 clusters (about 33 MiB
+/// with 512 byte clusters), if the device's sector size is not a power of two
+/// between 512 and 4096, or if `options.sectors_per_cluster` is invalid.
+/// Returns any error that occurs while writing to `device`.
+pub fn format_with<T: BlockDevice>(
+    mut device: T,
+    size: u64,
+    options: &FormatOptions
+) -> io::Result<()> {
+    let sector_size = device.sector_size();
+    if !sector_size.is_power_of_two() || sector_size < 512 || sector_size > 4096 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported sector size"));
+    }
+
+    let partition_start = PARTITION_OFFSET / sector_size;
+    let total_sectors = (size / sector_size).saturating_sub(partition_start);
+    if total_sectors > ::std::u32::MAX as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "device too large"));
+    }
+
+    let sectors_per_cluster = match options.sectors_per_cluster {
+        Some(n) if n.is_power_of_two() && n as u64 * sector_size <= 32 * 1024 => n,
+        Some(_) => {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "invalid number of sectors per cluster"));
+        }
+        None => default_sectors_per_cluster(total_sectors * sector_size, sector_size)
+    };
+
+    let (sectors_per_fat, num_clusters) = fat_size(
+        total_sectors, sector_size, sectors_per_cluster as u64);
+    // drivers tell FAT32 volumes apart from FAT12 and FAT16 volumes only by
+    // their number of clusters
+    if FatType::from_cluster_count(num_clusters) != FatType::Fat32 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "device too small for FAT32"));
+    } else if num_clusters > MAX_CLUSTERS {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "device too large for FAT32"));
+    }
+
+    write_mbr(&mut device, partition_start as u32, total_sectors as u32)?;
+
+    let bpb = BiosParameterBlock {
+        jmp: [0xEB, 0x58, 0x90],
+        oem_id: le_u64(b"MSWIN4.1"),
+        bytes_per_sector: sector_size as u16,
+        sectors_per_cluster,
+        num_reserved: NUM_RESERVED,
+        num_fat: NUM_FATS,
+        max_dirs: 0,
+        total_sectors_lo: 0,
+        fat_id: MEDIA_DESCRIPTOR,
+        sectors_per_fat: 0,
+        sectors_per_track: 63,
+        heads: 255,
+        hidden_sectors: partition_start as u32,
+        total_sectors_hi: total_sectors as u32,
+        sectors_per_fat32: sectors_per_fat as u32,
+        flags: 0,
+        fat_version: 0,
+        root: ROOT_CLUSTER,
+        fsinfo: FSINFO_SECTOR,
+        backup_boot: BACKUP_BOOT_SECTOR,
+        __reserved: [0; 12],
+        drive_number: 0x80,
+        windows_flags: 0,
+        signature: 0x29,
+        volume_id: options.volume_id,
+        volume_label: options.volume_label,
+        system_id: le_u64(b"FAT32   "),
+        boot_code: [0; 420],
+        bootable_signature: 0xAA55,
+    };
+    let bpb: [u8; 512] = unsafe { mem::transmute(bpb) };
+
+    let fsinfo = FsInfo {
+        lead_signature: 0x41615252,
+        __reserved: [0; 480],
+        struct_signature: 0x61417272,
+        free_count: num_clusters as u32 - 1,
+        next_free: ROOT_CLUSTER + 1,
+        __reserved2: [0; 12],
+        trail_signature: 0xAA550000,
+    };
+    let fsinfo: [u8; 512] = unsafe { mem::transmute(fsinfo) };
+
+    // The reserved sectors, every FAT and the root directory cluster.
+    let data_start = NUM_RESERVED as u64 + NUM_FATS as u64 * sectors_per_fat;
+    let zeroed = data_start + sectors_per_cluster as u64;
+    let zero = vec![0u8; sector_size as usize];
+    for sector in 0..zeroed {
+        device.write_sector(partition_start + sector, &zero)?;
+    }
+
+    let mut sector = vec![0u8; sector_size as usize];
+    for &boot in [0, BACKUP_BOOT_SECTOR as u64].iter() {
+        sector[..512].copy_from_slice(&bpb);
+        device.write_sector(partition_start + boot, &sector)?;
+        sector[..512].copy_from_slice(&fsinfo);
+        device.write_sector(partition_start + boot + FSINFO_SECTOR as u64, &sector)?;
+    }
+
+    let mut fat = vec![0u8; sector_size as usize];
+    fat[0..4].copy_from_slice(&to_le(0x0FFFFF00 | MEDIA_DESCRIPTOR as u32));
+    fat[4..8].copy_from_slice(&to_le(0x0FFFFFFF));
+    fat[8..12].copy_from_slice(&to_le(0x0FFFFFFF));
+    for i in 0..NUM_FATS as u64 {
+        device.write_sector(partition_start + NUM_RESERVED as u64 + i * sectors_per_fat, &fat)?;
+    }
+
+    if options.volume_label != *NO_LABEL {
+        let mut root = vec![0u8; sector_size as usize];
+        root[..11].copy_from_slice(&options.volume_label);
+        root[11] = Attributes::VOLUME_ID.0;
+        device.write_sector(partition_start + data_start, &root)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the number of sectors per cluster Microsoft's tools pick for a
+/// FAT32 volume of `volume_size` bytes.
+fn default_sectors_per_cluster(volume_size: u64, sector_size: u64) -> u8 {
+    const MIB: u64 = 1 << 20;
+    let cluster_size = match volume_size {
+        size if size <= 260 * MIB => 512,
+        size if size <= 8 * 1024 * MIB => 4 * 1024,
+        size if size <= 16 * 1024 * MIB => 8 * 1024,
+        size if size <= 32 * 1024 * MIB => 16 * 1024,
+        _ => 32 * 1024
+    };
+    ::std::cmp::max(1, cluster_size / sector_size) as u8
+}
+
+/// Returns the smallest number of sectors per FAT that can describe every
+/// cluster of a volume of `total_sectors` sectors, and that number of
+/// clusters.
+fn fat_size(total_sectors: u64, sector_size: u64, sectors_per_cluster: u64) -> (u64, u64) {
+    let overhead = NUM_RESERVED as u64;
+    let entries_per_sector = sector_size / 4;
+    let mut sectors_per_fat = 1;
+    loop {
+        let data_sectors = total_sectors
+            .saturating_sub(overhead + NUM_FATS as u64 * sectors_per_fat);
+        let num_clusters = data_sectors / sectors_per_cluster;
+        let needed = (num_clusters + 2 + entries_per_sector - 1) / entries_per_sector;
+        if needed <= sectors_per_fat {
+            return (sectors_per_fat, num_clusters);
+        }
+        sectors_per_fat = needed;
+    }
+}
+
+/// Writes an MBR with a single FAT32 partition of `num_sectors` sectors that
+/// starts at sector `start`.
+fn write_mbr<T: BlockDevice>(device: &mut T, start: u32, num_sectors: u32) -> io::Result<()> {
+    let mut mbr = MasterBootRecord::new();
+    let partition = PartitionEntry::new(PARTITION_TYPE_FAT32_LBA, start, num_sectors);
+    let device_sectors = start as u64 + num_sectors as u64;
+    let result = match mbr.set_partition(0, partition) {
+        Ok(()) => mbr.write(device, device_sectors),
+        Err(e) => Err(e)
+    };
+    match result {
+        Ok(()) => Ok(()),
+        Err(mbr::Error::Io(e)) => Err(e),
+        Err(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid partition"))
+    }
+}
+
+fn le_u64(bytes: &[u8; 8]) -> u64 {
+    bytes.iter().rev().fold(0, |value, &b| (value << 8) | b as u64)
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+use std::mem;
+
+use traits::BlockDevice;
+use vfat::Error;
+
+/// The value of `free_count` and `next_free` when they are unknown.
+pub const FSINFO_UNKNOWN: u32 = 0xFFFFFFFF;
+
+/// Byte offset of `free_count` in the FSInfo sector.
+pub(crate) const FREE_COUNT_OFFSET: usize = 488;
+
+/// Byte offset of `next_free` in the FSInfo sector.
+pub(crate) const NEXT_FREE_OFFSET: usize = 492;
+
+#[repr(C, packed)]
+pub struct FsInfo {
+    /// always 0x41615252
+    pub lead_signature: u32,
+    pub __reserved: [u8; 480],
+    /// always 0x61417272
+    pub struct_signature: u32,
+    /// last known number of free clusters, or 0xFFFFFFFF if unknown
+    pub free_count: u32,
+    /// cluster at which to start looking for free clusters, or 0xFFFFFFFF if
+    /// unknown
+    pub next_free: u32,
+    pub __reserved2: [u8; 12],
+    /// always 0xAA550000
+    pub trail_signature: u32
+}
+
+impl FsInfo {
+    /// Reads the FSInfo structure from sector `sector` of device `device`.
+    ///
+    /// # Errors
+    ///
+    /// If the lead, struct or trail signature is invalid, returns an error of
+    /// `BadSignature`.
+    pub fn from<T: BlockDevice>(mut device: T, sector: u64) -> Result<FsInfo, Error> {
+        let mut raw_fsinfo = [0u8; 512];
+        device.read_sector(sector, &mut raw_fsinfo)?;
+
+        let fsinfo: FsInfo = unsafe { mem::transmute(raw_fsinfo) };
+        if fsinfo.lead_signature != 0x41615252
+            || fsinfo.struct_signature != 0x61417272
+            || fsinfo.trail_signature != 0xAA550000 {
+            return Err(Error::BadSignature);
+        }
+        Ok(fsinfo)
+    }
+
+    /// Returns the free cluster count, or `None` if it is unknown.
+    pub fn free_count(&self) -> Option<u32> {
+        match self.free_count {
+            FSINFO_UNKNOWN => None,
+            count => Some(count)
+        }
+    }
+
+    /// Returns the next free cluster hint, or `None` if it is unknown.
+    pub fn next_free(&self) -> Option<u32> {
+        match self.next_free {
+            FSINFO_UNKNOWN => None,
+            next => Some(next)
+        }
+    }
+}
+
+impl fmt::Debug for FsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FsInfo")
+            .field("free_count", &{ self.free_count })
+            .field("next_free", &{ self.next_free })
+            .finish()
+    }
+}
@@ -1,28 +1,244 @@
 use std::fmt;
+use std::ops::{BitAnd, BitOr, Not};
 
 use traits;
+use traits::Timestamp as TimestampTrait;
 
 /// A date as represented in FAT32 on-disk structures.
 #[repr(C, packed)]
-#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Date(u16);
 
 /// Time as represented in FAT32 on-disk structures.
 #[repr(C, packed)]
-#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Time(u16);
 
 /// File attributes as represented in FAT32 on-disk structures.
 #[repr(C, packed)]
 #[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Attributes(pub u8);
- 
-/// A structure containing a date and time.
-#[repr(C, packed)]
-#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+
+impl Attributes {
+    pub const READ_ONLY: Attributes = Attributes(0x01);
+    pub const HIDDEN: Attributes = Attributes(0x02);
+    pub const SYSTEM: Attributes = Attributes(0x04);
+    pub const VOLUME_ID: Attributes = Attributes(0x08);
+    pub const DIRECTORY: Attributes = Attributes(0x10);
+    pub const ARCHIVE: Attributes = Attributes(0x20);
+    /// The combination of attributes that marks a long file name entry.
+    pub const LFN: Attributes = Attributes(0x0F);
+
+    /// Whether every attribute set in `other` is set in `self`.
+    pub fn contains(&self, other: Attributes) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn read_only(&self) -> bool {
+        !self.is_lfn() && self.contains(Attributes::READ_ONLY)
+    }
+
+    pub fn hidden(&self) -> bool {
+        !self.is_lfn() && self.contains(Attributes::HIDDEN)
+    }
+
+    pub fn system(&self) -> bool {
+        !self.is_lfn() && self.contains(Attributes::SYSTEM)
+    }
+
+    /// Whether the entry is the volume label.
+    pub fn volume_id(&self) -> bool {
+        !self.is_lfn() && self.contains(Attributes::VOLUME_ID)
+    }
+
+    pub fn directory(&self) -> bool {
+        !self.is_lfn() && self.contains(Attributes::DIRECTORY)
+    }
+
+    /// Whether the entry has changed since it was last backed up.
+    pub fn archive(&self) -> bool {
+        !self.is_lfn() && self.contains(Attributes::ARCHIVE)
+    }
+
+    /// Whether the entry is a long file name entry.
+    pub fn is_lfn(&self) -> bool {
+        self.0 & 0x3F == Attributes::LFN.0
+    }
+}
+
+impl BitOr for Attributes {
+    type Output = Attributes;
+
+    fn bitor(self, other: Attributes) -> Attributes {
+        Attributes(self.0 | other.0)
+    }
+}
+
+impl BitAnd for Attributes {
+    type Output = Attributes;
+
+    fn bitand(self, other: Attributes) -> Attributes {
+        Attributes(self.0 & other.0)
+    }
+}
+
+impl Not for Attributes {
+    type Output = Attributes;
+
+    fn not(self) -> Attributes {
+        Attributes(!self.0)
+    }
+}
+
+/// A structure containing a date and time. Timestamps are ordered
+/// chronologically.
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Timestamp {
-    pub time: Time,
     pub date: Date,
+    pub time: Time,
+    /// Hundredths of a second past `time`, in range [0, 200). Only creation
+    /// times store these on disk.
+    pub centiseconds: u8,
+}
+
+/// Seconds from the Unix epoch to `Timestamp::EPOCH`.
+const UNIX_SECONDS_AT_EPOCH: u64 = 315532800;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+impl Date {
+    /// Returns the date `year`-`month`-`day`, or `None` if it isn't a valid
+    /// date between 1980 and 2107.
+    pub fn new(year: usize, month: u8, day: u8) -> Option<Date> {
+        if year < 1980 || year > 2107 || month < 1 || month > 12
+                || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        Some(Date((((year - 1980) as u16) << 9) | ((month as u16) << 5) | day as u16))
+    }
+}
+
+impl Time {
+    /// Returns the time `hour`:`minute`:`second`, rounded down to an even
+    /// second as FAT stores it, or `None` if it isn't a valid time.
+    pub fn new(hour: u8, minute: u8, second: u8) -> Option<Time> {
+        if hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+        Some(Time(((hour as u16) << 11) | ((minute as u16) << 5) | (second / 2) as u16))
+    }
+}
+
+impl Timestamp {
+    /// The earliest timestamp representable on disk: 01/01/1980 00:00:00.
+    pub const EPOCH: Timestamp = Timestamp {
+        date: Date((1 << 5) | 1),
+        time: Time(0),
+        centiseconds: 0
+    };
+
+    /// Returns the timestamp for the given calendar fields, or `None` if they
+    /// aren't a valid date and time between 1980 and 2107.
+    pub fn new(
+        year: usize,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8
+    ) -> Option<Timestamp> {
+        Some(Timestamp {
+            date: Date::new(year, month, day)?,
+            time: Time::new(hour, minute, second)?,
+            centiseconds: (second % 2) * 100,
+        })
+    }
+
+    /// Returns the timestamp `seconds` seconds after the Unix epoch, or `None`
+    /// if it is before 1980 or after 2107. FAT timestamps have no time zone, so
+    /// the result is in UTC.
+    pub fn from_unix(seconds: u64) -> Option<Timestamp> {
+        if seconds < UNIX_SECONDS_AT_EPOCH {
+            return None;
+        }
+
+        let (year, month, day) = civil_from_days(seconds / SECONDS_PER_DAY);
+        let seconds = seconds % SECONDS_PER_DAY;
+        Timestamp::new(year as usize, month as u8, day as u8,
+                       (seconds / 3600) as u8, (seconds / 60 % 60) as u8, (seconds % 60) as u8)
+    }
+
+    /// Returns the number of whole seconds from the Unix epoch to `self`.
+    pub fn to_unix(&self) -> u64 {
+        let days = days_from_civil(self.year() as u64, self.month() as u64, self.day() as u64);
+        days * SECONDS_PER_DAY + self.hour() as u64 * 3600 + self.minute() as u64 * 60
+            + self.second() as u64
+    }
+
+    /// Hundredths of a second past `second()`. Always in range [0, 100).
+    pub fn centisecond(&self) -> u8 {
+        self.centiseconds % 100
+    }
+
+    /// Returns the current time according to the host's system clock, or
+    /// `EPOCH` if the clock is set before it. Can be used with
+    /// `VFat::set_clock()`.
+    #[cfg(not(target_os = "ros"))]
+    pub fn now() -> Timestamp {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let elapsed = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed,
+            Err(_) => return Timestamp::EPOCH
+        };
+        match Timestamp::from_unix(elapsed.as_secs()) {
+            Some(now) => Timestamp {
+                centiseconds: now.centiseconds + (elapsed.subsec_nanos() / 10_000_000) as u8,
+                ..now
+            },
+            None => Timestamp::EPOCH
+        }
+    }
+}
+
+fn is_leap_year(year: usize) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: usize, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+/// Returns the number of days from 1970-01-01 to `year`-`month`-`day`, which
+/// must not be before 1970.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    // count years from March so that leap days fall at the end of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day.saturating_sub(1);
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Returns the year, month and day that is `days` days after 1970-01-01.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+        - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
 }
 
 /// Metadata for a directory entry.
@@ -31,6 +247,9 @@ pub struct Metadata {
     pub name: String,
     pub size: u32,
     pub attributes: Attributes,
+    /// Whether `name` was decoded lossily because the long file name contains
+    /// an unpaired surrogate.
+    pub lossy_name: bool,
     pub created: Timestamp,
     pub accessed: Date,
     pub last_modified: Timestamp,
@@ -57,20 +276,59 @@ impl traits::Timestamp for Timestamp {
     }
 
     fn second(&self) -> u8 {
-        2 * (self.time.0 & 0b11111) as u8
+        2 * (self.time.0 & 0b11111) as u8 + self.centiseconds / 100
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+               self.year(), self.month(), self.day(),
+               self.hour(), self.minute(), self.second())?;
+        match self.centisecond() {
+            0 => Ok(()),
+            centisecond => write!(f, ".{:02}", centisecond)
+        }
+    }
+}
+
+impl Metadata {
+    /// Sets the time the entry was created, to a precision of 10 ms. Files
+    /// write the change to disk on `sync()`.
+    pub fn set_created(&mut self, created: Timestamp) {
+        self.created = created;
+    }
+
+    /// Sets the time the entry was last modified, to a precision of 2
+    /// seconds. Files write the change to disk on `sync()`.
+    pub fn set_modified(&mut self, modified: Timestamp) {
+        self.last_modified = Timestamp { centiseconds: 0, ..modified };
+    }
+
+    /// Sets the date the entry was last accessed. Only the date is stored.
+    /// Files write the change to disk on `sync()`.
+    pub fn set_accessed(&mut self, accessed: Timestamp) {
+        self.accessed = accessed.date;
     }
 }
 
-// TODO: use constants for attribute masks
 impl traits::Metadata for Metadata {
     type Timestamp = Timestamp;
 
     fn read_only(&self) -> bool {
-        self.attributes.0 & 0x01 != 0
+        self.attributes.read_only()
     }
 
     fn hidden(&self) -> bool {
-        self.attributes.0 & 0x02 != 0
+        self.attributes.hidden()
+    }
+
+    fn system(&self) -> bool {
+        self.attributes.system()
+    }
+
+    fn archive(&self) -> bool {
+        self.attributes.archive()
     }
 
     fn created(&self) -> Self::Timestamp {
@@ -78,15 +336,15 @@ impl traits::Metadata for Metadata {
     }
 
     fn accessed(&self) -> Self::Timestamp {
-        Timestamp { date: self.accessed, time: Time(0) }
+        Timestamp { date: self.accessed, time: Time(0), centiseconds: 0 }
     }
 
     fn modified(&self) -> Self::Timestamp {
         self.last_modified
     }
 
-    fn size(&self) -> u32 {
-        self.size
+    fn size(&self) -> u64 {
+        self.size as u64
     }
 }
 
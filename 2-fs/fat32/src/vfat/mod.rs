@@ -2,23 +2,34 @@ pub(crate) mod file;
 pub(crate) mod dir;
 pub(crate) mod vfat;
 pub(crate) mod ebpb;
+pub(crate) mod fsinfo;
 pub(crate) mod error;
 pub(crate) mod cluster;
 pub(crate) mod fat;
 pub(crate) mod entry;
 pub(crate) mod metadata;
 pub(crate) mod cache;
+pub(crate) mod dentry;
 pub(crate) mod shared;
+pub(crate) mod check;
+pub(crate) mod format;
+pub(crate) mod cp437;
 
 pub use self::ebpb::BiosParameterBlock;
+pub use self::fsinfo::FsInfo;
 pub use self::file::File;
 pub use self::dir::Dir;
 pub use self::error::Error;
-pub use self::vfat::VFat;
+pub use self::vfat::{VFat, StatFs, MountOptions, MirrorCheck};
 pub use self::entry::Entry;
 pub use self::metadata::{Metadata, Attributes, Date, Time, Timestamp};
 pub use self::shared::Shared;
+pub use self::cache::CacheStats;
+pub use self::check::Problem;
+pub use self::format::{format, format_with, FormatOptions};
 
 pub(crate) use self::cache::{CachedDevice, Partition};
+pub(crate) use self::dentry::{DentryCache, CachedEntry};
+pub use self::fat::FatType;
 pub(crate) use self::fat::{Status, FatEntry};
 pub(crate) use self::cluster::Cluster;
@@ -1,80 +1,672 @@
 use std::io;
+use std::ffi::OsStr;
 use std::path::{Component, Path};
 use std::mem::size_of;
 use std::cmp::min;
 
-use util::{SliceExt, from_le};
+use util::{SliceExt, from_le, to_le};
 use mbr::MasterBootRecord;
-use vfat::{Shared, Cluster, File, Dir, Entry, FatEntry, Error, Status};
-use vfat::{BiosParameterBlock, CachedDevice, Partition};
+use gpt::GuidPartitionTable;
+use vfat::{Shared, Cluster, File, Dir, Entry, FatEntry, FatType, Error, Status, Timestamp};
+use vfat::{BiosParameterBlock, CachedDevice, CacheStats, DentryCache, FsInfo, Partition};
+use vfat::fsinfo::{FREE_COUNT_OFFSET, FSINFO_UNKNOWN, NEXT_FREE_OFFSET};
+use vfat::dir::{self, BYTES_IN_ENTRY, ENTRY_DELETED, EntryLocation, VFatRegularDirEntry};
 use traits;
 use traits::{FileSystem, BlockDevice};
 
-const FAT_ENTRY_SIZE: u16 = 4;
+/// The value written to a FAT entry to mark the end of a cluster chain.
+pub(crate) const EOC: Status = Status::Eoc(0x0FFFFFFF);
+
+/// The label in the boot sector of a volume without a label.
+pub(crate) const NO_LABEL: &[u8; 11] = b"NO NAME    ";
+
+/// Space usage of a mounted file system, as returned by `VFat::statfs()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StatFs {
+    /// The total number of data clusters.
+    pub total_clusters: u32,
+    /// The number of unallocated data clusters.
+    pub free_clusters: u32,
+    /// The size of a cluster in bytes.
+    pub cluster_size: u32,
+}
+
+impl StatFs {
+    /// The total size of the data region in bytes.
+    pub fn total_bytes(&self) -> u64 {
+        self.total_clusters as u64 * self.cluster_size as u64
+    }
+
+    /// The number of unallocated bytes in the data region.
+    pub fn free_bytes(&self) -> u64 {
+        self.free_clusters as u64 * self.cluster_size as u64
+    }
+}
+
+/// What to do about FAT copies that differ from the first FAT when mounting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MirrorCheck {
+    /// Don't compare the copies.
+    Skip,
+    /// Compare the copies and record the differences, which are returned by
+    /// `VFat::fat_mismatches()`.
+    Report,
+    /// Like `Report`, and also overwrite the differing copies with the first
+    /// FAT.
+    Repair,
+}
+
+impl Default for MirrorCheck {
+    fn default() -> MirrorCheck {
+        MirrorCheck::Skip
+    }
+}
+
+/// Options for mounting a volume with `VFat::from_with()`.
+#[derive(Debug, Default, Clone)]
+pub struct MountOptions {
+    /// Whether to compare the FAT copies when mounting. Copies are only
+    /// compared if FAT mirroring is enabled.
+    pub fat_mirror: MirrorCheck,
+}
 
 #[derive(Debug)]
 pub struct VFat {
     device: CachedDevice,
+    /// The entries found by recent lookups in `Dir::find`.
+    pub(crate) dentries: DentryCache,
     bytes_per_sector: u16,
     sectors_per_cluster: u8,
     sectors_per_fat: u32,
+    num_fats: u8,
+    /// The only FAT that is read and written if FAT mirroring is disabled, or
+    /// `None` if every FAT is written.
+    active_fat: Option<u8>,
+    /// The FAT copies and clusters that differed from the first FAT when the
+    /// volume was mounted.
+    fat_mismatches: Vec<(u8, Cluster)>,
+    num_clusters: u32,
+    fat_type: FatType,
     fat_start_sector: u64,
+    /// The first sector of the fixed root directory region of FAT12 and FAT16
+    /// volumes. On FAT32 volumes, this is equal to `data_start_sector`.
+    root_dir_start_sector: u64,
+    /// The number of entries in the fixed root directory region, or 0 on FAT32
+    /// volumes.
+    root_dir_entries: u32,
     data_start_sector: u64,
+    /// The first cluster of the root directory, or cluster 0 if the root
+    /// directory is the fixed root directory region.
     root_dir_cluster: Cluster,
+    clock: fn() -> Timestamp,
+    /// The logical sector of the FSInfo structure, if the volume has a valid
+    /// one.
+    fsinfo_sector: Option<u64>,
+    /// The logical sector of the boot sector and of its backup copy, if the
+    /// volume has one.
+    boot_sector: u64,
+    backup_boot_sector: Option<u64>,
+    /// The offset of the volume label in the boot sector, if the extended
+    /// boot signature says the boot sector has one.
+    label_offset: Option<usize>,
+    volume_id: Option<u32>,
+    /// The number of free clusters, if known.
+    free_count: Option<u32>,
+    /// The cluster at which to start searching for a free cluster.
+    next_free: u32,
+    /// Whether `free_count` or `next_free` changed since the last sync.
+    fsinfo_dirty: bool,
 }
 
 impl VFat {
-    pub fn from<T>(mut device: T) -> Result<Shared<VFat>, Error>
+    /// Mounts the FAT32 file system on `device`.
+    ///
+    /// If sector 0 of `device` is a FAT32 boot sector, the device is treated
+    /// as a "superfloppy" without a partition table. Otherwise the first FAT32
+    /// partition in the device's GPT, if it has a protective MBR, or MBR is
+    /// mounted.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there is no FAT32 partition and an error of the
+    /// failing component if the partition table or file system is invalid.
+    pub fn from<T>(device: T) -> Result<Shared<VFat>, Error>
         where T: BlockDevice + 'static
     {
+        VFat::from_with(device, &MountOptions::default())
+    }
+
+    /// Mounts the FAT file system on `device` like `from()`, using `options`.
+    pub fn from_with<T>(mut device: T, options: &MountOptions) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        if let Ok(bpb) = BiosParameterBlock::from(&mut device, 0) {
+            if bpb.is_plausible() {
+                return VFat::mount(device, 0, options);
+            }
+        }
+
         let mbr = MasterBootRecord::from(&mut device)?;
+        let partition_offset = match mbr.is_protective() {
+            true => GuidPartitionTable::from(&mut device)?.get_fat_partition_offset(),
+            false => mbr.get_fat_partition_offset().map(|offset| offset as u64)
+        };
+        match partition_offset {
+            Some(offset) => VFat::mount(device, offset, options),
+            None => Err(Error::NotFound)
+        }
+    }
 
-        let bpb_offset = match mbr.get_fat_partition_offset() {
-            Some(offset) => offset,
-            None => { return Err(Error::NotFound); }
+    /// Mounts the file system in partition `index` of `device`, regardless of
+    /// the partition's type. For a disk with an MBR, `index` is the index of
+    /// the entry in the partition table. For a disk with a GPT, `index` is the
+    /// index in the list of used partitions, `GuidPartitionTable::partitions()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there is no such partition and an error of the
+    /// failing component if the partition table or file system is invalid.
+    pub fn from_partition<T>(mut device: T, index: usize) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        let mbr = MasterBootRecord::from(&mut device)?;
+        let partition_offset = match mbr.is_protective() {
+            true => GuidPartitionTable::from(&mut device)?.partitions()
+                .get(index)
+                .map(|partition| partition.first_lba),
+            false => mbr.partitions()
+                .get(index)
+                .and_then(|partition| match partition.is_used() {
+                    true => Some(partition.relative_sector() as u64),
+                    false => None
+                })
         };
+        match partition_offset {
+            Some(offset) => VFat::mount(device, offset, &MountOptions::default()),
+            None => Err(Error::NotFound)
+        }
+    }
 
-        let bpb = BiosParameterBlock::from(&mut device, bpb_offset as u64)?;
-        let fat_start_sector = bpb_offset as u64 + bpb.num_reserved as u64;
-        let data_start_sector = fat_start_sector +
-            (bpb.sectors_per_fat32 as u64) *
-            (bpb.num_fat as u64);
+    /// Mounts the file system whose boot sector is at sector `bpb_offset` of
+    /// `device`.
+    fn mount<T>(
+        mut device: T,
+        bpb_offset: u64,
+        options: &MountOptions
+    ) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        let bpb = BiosParameterBlock::from(&mut device, bpb_offset)?;
+        bpb.validate()?;
+        if bpb.bytes_per_sector as u64 % device.sector_size() != 0 {
+            return Err(Error::InvalidBpb("sector smaller than the device's sectors"));
+        }
 
-        Ok(Shared::new(VFat {
-            device: CachedDevice::new(
-                device,
-                Partition {
-                    start: bpb_offset as u64,
-                    sector_size: bpb.bytes_per_sector as u64
-                }),
+        // FAT32 volumes leave the 16-bit FAT size empty.
+        let sectors_per_fat = match bpb.sectors_per_fat {
+            0 => bpb.sectors_per_fat32 as u64,
+            n => n as u64
+        };
+        let root_dir_entries = match bpb.sectors_per_fat {
+            0 => 0,
+            _ => bpb.max_dirs as u64
+        };
+        let root_dir_sectors = (root_dir_entries * BYTES_IN_ENTRY as u64 +
+            bpb.bytes_per_sector as u64 - 1) / bpb.bytes_per_sector as u64;
+
+        let fat_start_sector = bpb_offset + bpb.num_reserved as u64;
+        let root_dir_start_sector = fat_start_sector +
+            sectors_per_fat * (bpb.num_fat as u64);
+        let data_start_sector = root_dir_start_sector + root_dir_sectors;
+
+        let total_sectors = match bpb.total_sectors_lo {
+            0 => bpb.total_sectors_hi as u64,
+            lo => lo as u64,
+        };
+        let data_sectors = total_sectors
+            .saturating_sub(data_start_sector - bpb_offset);
+        let data_clusters = data_sectors / bpb.sectors_per_cluster as u64;
+
+        // The FAT type is determined by the number of clusters alone, and the
+        // boot sector must have the layout of that type.
+        let fat_type = FatType::from_cluster_count(data_clusters);
+        if (fat_type == FatType::Fat32) != (bpb.sectors_per_fat == 0) {
+            return Err(Error::InvalidBpb("layout does not match the number of clusters"));
+        }
+        // FAT32 volumes can disable mirroring and keep only one FAT current.
+        let active_fat = match fat_type {
+            FatType::Fat32 if bpb.flags & 0x80 != 0 => Some((bpb.flags & 0x0F) as u8),
+            _ => None
+        };
+        if active_fat.map_or(false, |fat| fat >= bpb.num_fat) {
+            return Err(Error::InvalidBpb("active FAT"));
+        }
+
+        let fat_capacity = fat_type.entries_in(sectors_per_fat * bpb.bytes_per_sector as u64);
+        let num_clusters = min(data_clusters, fat_capacity.saturating_sub(2));
+        if fat_type == FatType::Fat32 && (bpb.root < 2 || bpb.root as u64 >= num_clusters + 2) {
+            return Err(Error::InvalidBpb("root directory cluster"));
+        }
+
+        let mut device = CachedDevice::new(
+            device,
+            Partition {
+                start: bpb_offset,
+                sector_size: bpb.bytes_per_sector as u64
+            });
+
+        // The extended boot signature is 0x29 if the boot sector holds a
+        // volume ID and label, or 0x28 if it only holds a volume ID.
+        let ebpb_offset = match fat_type {
+            FatType::Fat32 => 64,
+            _ => 36
+        };
+        let (volume_id, label_offset) = {
+            let boot = device.get(bpb_offset)?;
+            let volume_id = from_le(&boot[ebpb_offset + 3..ebpb_offset + 7]);
+            match boot[ebpb_offset + 2] {
+                0x29 => (Some(volume_id), Some(ebpb_offset + 7)),
+                0x28 => (Some(volume_id), None),
+                _ => (None, None)
+            }
+        };
+        let backup_boot = bpb.backup_boot as u64;
+        let backup_boot_sector = match fat_type {
+            FatType::Fat32 if backup_boot != 0 && backup_boot < bpb.num_reserved as u64 =>
+                Some(bpb_offset + backup_boot),
+            _ => None
+        };
+
+        // A missing or corrupt FSInfo structure only costs us its hints.
+        let fsinfo_sector = match bpb.fsinfo {
+            _ if fat_type != FatType::Fat32 => None,
+            0 | 0xFFFF => None,
+            sector => Some(bpb_offset + sector as u64)
+        };
+        let fsinfo = match fsinfo_sector {
+            Some(sector) => match FsInfo::from(&mut device, sector) {
+                Ok(fsinfo) => Some(fsinfo),
+                Err(Error::BadSignature) => None,
+                Err(e) => return Err(e)
+            },
+            None => None
+        };
+        let free_count = match fsinfo.as_ref().and_then(|fsinfo| fsinfo.free_count()) {
+            Some(count) if count as u64 <= num_clusters => Some(count),
+            _ => None
+        };
+        let next_free = match fsinfo.as_ref().and_then(|fsinfo| fsinfo.next_free()) {
+            Some(next) if next >= 2 && (next as u64) < num_clusters + 2 => next,
+            _ => 2
+        };
+
+        let mut vfat = VFat {
+            device,
+            dentries: DentryCache::new(),
             bytes_per_sector: bpb.bytes_per_sector as u16,
             sectors_per_cluster: bpb.sectors_per_cluster,
-            sectors_per_fat: bpb.sectors_per_fat32 as u32,
+            sectors_per_fat: sectors_per_fat as u32,
+            num_fats: bpb.num_fat,
+            active_fat,
+            fat_mismatches: Vec::new(),
+            num_clusters: num_clusters as u32,
+            fat_type,
             fat_start_sector,
+            root_dir_start_sector,
+            root_dir_entries: root_dir_entries as u32,
             data_start_sector,
-            root_dir_cluster: Cluster::from(bpb.root)
-        }))
+            root_dir_cluster: match fat_type {
+                FatType::Fat32 => Cluster::from(bpb.root),
+                _ => Cluster(0)
+            },
+            clock: || Timestamp::EPOCH,
+            fsinfo_sector: fsinfo.and(fsinfo_sector),
+            boot_sector: bpb_offset,
+            backup_boot_sector,
+            label_offset,
+            volume_id,
+            free_count,
+            next_free,
+            fsinfo_dirty: false,
+        };
+
+        vfat.fat_mismatches = match options.fat_mirror {
+            MirrorCheck::Skip => Vec::new(),
+            MirrorCheck::Report => vfat.compare_fats(false)?,
+            MirrorCheck::Repair => {
+                let mismatches = vfat.compare_fats(true)?;
+                vfat.device.sync()?;
+                mismatches
+            }
+        };
+        Ok(Shared::new(vfat))
+    }
+
+    /// Sets the function used to timestamp entries that are created or
+    /// modified. Defaults to a clock that always reports `Timestamp::EPOCH`.
+    pub fn set_clock(&mut self, clock: fn() -> Timestamp) {
+        self.clock = clock;
+    }
+
+    /// The first cluster of the root directory. This is cluster 0 if the
+    /// root directory is the fixed root directory region of a FAT12 or FAT16
+    /// volume.
+    pub(crate) fn root_cluster(&self) -> Cluster {
+        self.root_dir_cluster
+    }
+
+    /// The type of the volume's FAT.
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+
+    /// The volume serial number, if the boot sector records one.
+    pub fn volume_id(&self) -> Option<u32> {
+        self.volume_id
+    }
+
+    /// Returns the volume label with trailing padding removed, or `None` if the
+    /// volume has no label. The label in the root directory takes precedence
+    /// over the copy in the boot sector.
+    pub fn label(&mut self) -> io::Result<Option<String>> {
+        let label = match dir::find_volume_entry(self)? {
+            Some((_, entry)) => entry.short_name(),
+            None => match self.label_offset {
+                Some(offset) => {
+                    let mut label = [0; 11];
+                    label.copy_from_slice(&self.device.get(self.boot_sector)?[offset..offset + 11]);
+                    if &label == NO_LABEL {
+                        return Ok(None);
+                    }
+                    label
+                }
+                None => return Ok(None)
+            }
+        };
+
+        let label = dir::decode_label(&label);
+        Ok(match label.is_empty() {
+            true => None,
+            false => Some(label)
+        })
+    }
+
+    /// Sets the volume label to `label`, or removes it if `label` is empty.
+    /// Both the volume label entry in the root directory and the copies in the
+    /// boot sectors are updated. Lower case letters are converted to upper
+    /// case.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidInput` if `label` is longer than 11
+    /// characters or contains characters that are not allowed in short names.
+    /// Returns an error of `Other` if the root directory is full.
+    pub fn set_label(&mut self, label: &str) -> io::Result<()> {
+        let label = dir::volume_label(label)?;
+        dir::write_volume_entry(self, label.as_ref())?;
+
+        if let Some(offset) = self.label_offset {
+            let label = label.as_ref().unwrap_or(NO_LABEL);
+            let sectors = Some(self.boot_sector).into_iter().chain(self.backup_boot_sector);
+            for sector in sectors {
+                self.device.get_mut(sector)?[offset..offset + 11].copy_from_slice(label);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the first cluster of the directory that starts at `dir`, or
+    /// `None` if the directory is the fixed root directory region.
+    ///
+    /// Directory entries refer to the root directory as cluster 0, so cluster
+    /// 0 is resolved to the root directory's first cluster.
+    pub(crate) fn dir_cluster(&self, dir: Cluster) -> Option<Cluster> {
+        match dir.0 {
+            0 if self.root_dir_entries > 0 => None,
+            0 => Some(self.root_dir_cluster),
+            _ => Some(dir)
+        }
+    }
+
+    /// Returns the cluster the dentry cache keeps the entries of the directory
+    /// starting at `dir` under, which is the same for cluster 0 and the first
+    /// cluster of a FAT32 root directory.
+    pub(crate) fn dentry_dir(&self, dir: Cluster) -> Cluster {
+        self.dir_cluster(dir).unwrap_or(dir)
+    }
+
+    /// Returns the current time according to the file system's clock.
+    pub(crate) fn now(&self) -> Timestamp {
+        (self.clock)()
+    }
+
+    /// Writes all dirty cached sectors back to the underlying device, after
+    /// updating the free cluster count and next free cluster hint in the
+    /// FSInfo structure.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing any sector to the disk fails.
+    pub fn sync(&mut self) -> io::Result<()> {
+        if let (true, Some(sector)) = (self.fsinfo_dirty, self.fsinfo_sector) {
+            let free_count = self.free_count.unwrap_or(FSINFO_UNKNOWN);
+            let next_free = self.next_free;
+            let data = self.device.get_mut(sector)?;
+            data[FREE_COUNT_OFFSET..FREE_COUNT_OFFSET + 4].copy_from_slice(&to_le(free_count));
+            data[NEXT_FREE_OFFSET..NEXT_FREE_OFFSET + 4].copy_from_slice(&to_le(next_free));
+        }
+        self.fsinfo_dirty = false;
+        self.device.sync()
+    }
+
+    /// Returns the total and free number of clusters and the cluster size.
+    ///
+    /// The free count is taken from the FSInfo structure when it is valid.
+    /// Otherwise the FAT is scanned once and the result is remembered.
+    pub fn statfs(&mut self) -> io::Result<StatFs> {
+        let free_clusters = match self.free_count {
+            Some(count) => count,
+            None => {
+                let count = self.count_free_clusters()?;
+                self.free_count = Some(count);
+                self.fsinfo_dirty = true;
+                count
+            }
+        };
+
+        Ok(StatFs {
+            total_clusters: self.num_clusters,
+            free_clusters,
+            cluster_size: self.cluster_size() as u32,
+        })
+    }
+
+    /// Counts the free clusters by scanning the FAT.
+    fn count_free_clusters(&mut self) -> io::Result<u32> {
+        let mut count = 0;
+        for raw in 2..self.num_clusters + 2 {
+            if self.fat_entry(Cluster(raw))?.status() == Status::Free {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Replaces the free cluster count and next free cluster hint with values
+    /// computed from the FAT.
+    pub(crate) fn recount_free_clusters(&mut self) -> io::Result<()> {
+        self.free_count = Some(self.count_free_clusters()?);
+        self.next_free = 2;
+        self.fsinfo_dirty = true;
+        Ok(())
+    }
+
+    /// The number of data clusters. Valid cluster numbers are `2` through
+    /// `num_clusters() + 1`.
+    pub(crate) fn num_clusters(&self) -> u32 {
+        self.num_clusters
+    }
+
+    /// Whether changes to the FAT are written to every copy of the FAT.
+    pub fn mirroring(&self) -> bool {
+        self.active_fat.is_none()
+    }
+
+    /// The index of the FAT that is read. This is 0 unless mirroring is
+    /// disabled.
+    pub fn active_fat(&self) -> u8 {
+        self.active_fat.unwrap_or(0)
+    }
+
+    /// The index of the FAT copy and the cluster of each FAT entry that
+    /// differed from the first FAT when the volume was mounted with
+    /// `MirrorCheck::Report` or `MirrorCheck::Repair`.
+    pub fn fat_mismatches(&self) -> &[(u8, Cluster)] {
+        &self.fat_mismatches
+    }
+
+    /// Compares every copy of the FAT against the first one and returns the
+    /// index of the copy and the cluster of each entry that differs. If
+    /// `repair` is `true`, differing sectors are overwritten with the
+    /// contents of the first FAT.
+    ///
+    /// If mirroring is disabled, the copies are expected to differ and
+    /// nothing is compared.
+    pub(crate) fn compare_fats(&mut self, repair: bool) -> io::Result<Vec<(u8, Cluster)>> {
+        if !self.mirroring() {
+            return Ok(Vec::new());
+        }
+
+        let bits = self.fat_type.bits() as u64;
+        let sector_size = self.bytes_per_sector as u64;
+        let end = self.num_clusters as u64 + 2;
+        let mut mismatches = Vec::new();
+        for index in 0..self.sectors_per_fat as u64 {
+            if index * sector_size * 8 / bits >= end {
+                break;
+            }
+
+            let primary = self.device.get(self.fat_start_sector + index)?.to_vec();
+            for fat in 1..self.num_fats {
+                let sector = self.fat_start_sector + fat as u64 * self.sectors_per_fat as u64 + index;
+                if self.device.get(sector)? == &primary[..] {
+                    continue;
+                }
+
+                let copy = self.device.get(sector)?.to_vec();
+                for i in (0..primary.len()).filter(|&i| primary[i] != copy[i]) {
+                    let cluster = Cluster(((index * sector_size + i as u64) * 8 / bits) as u32);
+                    if (cluster.0 as u64) < end && mismatches.last() != Some(&(fat, cluster)) {
+                        mismatches.push((fat, cluster));
+                    }
+                }
+
+                if repair {
+                    self.device.get_mut(sector)?.copy_from_slice(&primary);
+                }
+            }
+        }
+        Ok(mismatches)
+    }
+
+    /// The size of a cluster in bytes.
+    pub(crate) fn cluster_size(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+
+    /// Checks that `cluster` is one of the volume's data clusters.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if it is not.
+    fn check_cluster(&self, cluster: Cluster) -> io::Result<()> {
+        if cluster.0 < 2 || cluster.0 >= self.num_clusters + 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "cluster out of range"));
+        }
+        Ok(())
+    }
+
+    /// The first logical sector of the data cluster `cluster`.
+    fn cluster_sector(&self, cluster: Cluster) -> u64 {
+        self.data_start_sector as u64 +
+            (cluster.0.saturating_sub(2)) as u64 *
+            self.sectors_per_cluster as u64
     }
 
-    /// A method to read from an offset of a cluster into a buffer
-    fn read_cluster(
+    /// Returns the sector cache's hit, miss and eviction counts.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.device.stats()
+    }
+
+    /// Returns the directory entry cache's hit, miss and eviction counts.
+    pub fn dentry_cache_stats(&self) -> CacheStats {
+        self.dentries.stats()
+    }
+
+    /// Sets the maximum number of sectors kept in the sector cache, writing
+    /// back and evicting sectors if more than `capacity` are cached.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing back an evicted dirty sector fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn set_cache_capacity(&mut self, capacity: usize) -> io::Result<()> {
+        self.device.set_capacity(capacity)
+    }
+
+    /// A method to read from an offset of a cluster into a buffer. Reads until
+    /// `buf` is full or the end of the cluster is reached, whichever comes
+    /// first, and returns the number of bytes read.
+    pub(crate) fn read_cluster(
        &mut self,
        cluster: Cluster,
-       // offset: usize, TODO: WAT?
+       offset: usize,
        buf: &mut [u8]
     ) -> io::Result<usize> {
-        let start_read_sector = self.data_start_sector as u64 +
-            (cluster.0.saturating_sub(2)) as u64 * 
-            self.sectors_per_cluster as u64;
-        let mut bytes_read = 0;
-        for i in 0..self.sectors_per_cluster {
-            let start_byte = (i as u16 * self.bytes_per_sector) as usize;
-            bytes_read += self.device.read_sector(
-                start_read_sector + i as u64, 
-                &mut buf[start_byte..start_byte + self.bytes_per_sector as usize]
-            )?;
+        self.check_cluster(cluster)?;
+        let sector_size = self.bytes_per_sector as usize;
+        let start_sector = self.cluster_sector(cluster);
+        let end = min(self.cluster_size(), offset + buf.len());
+
+        let mut pos = offset;
+        while pos < end {
+            let sector_offset = pos % sector_size;
+            let amount = min(sector_size - sector_offset, end - pos);
+            let data = self.device.get(start_sector + (pos / sector_size) as u64)?;
+            buf[pos - offset..pos - offset + amount]
+                .copy_from_slice(&data[sector_offset..sector_offset + amount]);
+            pos += amount;
         }
-        Ok(bytes_read)
+        Ok(end.saturating_sub(offset))
+    }
+
+    /// A method to write from a buffer into an offset of a cluster. Writes
+    /// until `buf` is exhausted or the end of the cluster is reached,
+    /// whichever comes first, and returns the number of bytes written.
+    pub(crate) fn write_cluster(
+       &mut self,
+       cluster: Cluster,
+       offset: usize,
+       buf: &[u8]
+    ) -> io::Result<usize> {
+        self.check_cluster(cluster)?;
+        let sector_size = self.bytes_per_sector as usize;
+        let start_sector = self.cluster_sector(cluster);
+        let end = min(self.cluster_size(), offset + buf.len());
+
+        let mut pos = offset;
+        while pos < end {
+            let sector_offset = pos % sector_size;
+            let amount = min(sector_size - sector_offset, end - pos);
+            let data = self.device.get_mut(start_sector + (pos / sector_size) as u64)?;
+            data[sector_offset..sector_offset + amount]
+                .copy_from_slice(&buf[pos - offset..pos - offset + amount]);
+            pos += amount;
+        }
+        Ok(end.saturating_sub(offset))
     }
 
     // TODO: The following methods may be useful here:
@@ -89,8 +681,14 @@ impl VFat {
     ) -> io::Result<usize> {
         let mut cluster_cursor = start;
         let mut bytes_read = 0usize;
+        let mut num_clusters = 0;
 
         loop {
+            num_clusters += 1;
+            if num_clusters > self.num_clusters {
+                return Err(cycle());
+            }
+
             let fat_entry = self.fat_entry(cluster_cursor)?;
             cluster_cursor = match fat_entry.status() {
                 Status::Data(next) => {
@@ -99,7 +697,7 @@ impl VFat {
                         self.bytes_per_sector as usize *
                         self.sectors_per_cluster as usize);
                     bytes_read += self.read_cluster(
-                        cluster_cursor, &mut buf[bytes_read..])?;
+                        cluster_cursor, 0, &mut buf[bytes_read..])?;
                     next
                 },
                 Status::Eoc(_) => {
@@ -108,7 +706,7 @@ impl VFat {
                         self.bytes_per_sector as usize *
                         self.sectors_per_cluster as usize);
                     bytes_read += self.read_cluster(
-                        cluster_cursor, &mut buf[bytes_read..])?;
+                        cluster_cursor, 0, &mut buf[bytes_read..])?;
 
                     return Ok(bytes_read); 
                 }
@@ -122,22 +720,399 @@ impl VFat {
         }
     }
 
-    /// A method to return a reference to a `FatEntry` for a cluster where the
-    /// reference points directly into a cached sector.
-    fn fat_entry(&mut self, cluster: Cluster) -> io::Result<FatEntry> {
-        let entries_per_sector = (self.bytes_per_sector / FAT_ENTRY_SIZE) as u32;
-        // index of the sector that contains this cluster. e.g. if there are
-        // 10 fat entries per sector and we want sector 12, this should be 1
-        let fat_sector_index = cluster.0 / entries_per_sector;
-        // index of the entry within the given sector, e.g. if we have the
-        // sector with entries 10-20 and we want sectore 12, this should be 2
-        let fat_entry_index = cluster.0 % entries_per_sector;
+    /// Returns the logical sector and the byte offset within that sector of
+    /// byte `offset` of FAT copy `fat`.
+    fn fat_byte_position(&self, fat: u8, offset: u64) -> (u64, usize) {
+        let sector_size = self.bytes_per_sector as u64;
+        (self.fat_start_sector + fat as u64 * self.sectors_per_fat as u64 + offset / sector_size,
+         (offset % sector_size) as usize)
+    }
+
+    /// Reads `buf.len()` bytes starting at byte `offset` of FAT copy `fat`.
+    /// FAT12 entries may span two sectors.
+    fn read_fat_bytes(&mut self, fat: u8, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let mut pos = 0;
+        while pos < buf.len() {
+            let (sector, idx) = self.fat_byte_position(fat, offset + pos as u64);
+            let data = self.device.get(sector)?;
+            let amount = min(data.len() - idx, buf.len() - pos);
+            buf[pos..pos + amount].copy_from_slice(&data[idx..idx + amount]);
+            pos += amount;
+        }
+        Ok(())
+    }
+
+    /// Writes `buf` starting at byte `offset` of FAT copy `fat`.
+    fn write_fat_bytes(&mut self, fat: u8, offset: u64, buf: &[u8]) -> io::Result<()> {
+        let mut pos = 0;
+        while pos < buf.len() {
+            let (sector, idx) = self.fat_byte_position(fat, offset + pos as u64);
+            let data = self.device.get_mut(sector)?;
+            let amount = min(data.len() - idx, buf.len() - pos);
+            data[idx..idx + amount].copy_from_slice(&buf[pos..pos + amount]);
+            pos += amount;
+        }
+        Ok(())
+    }
+
+    /// The number of bytes read or written to access a FAT entry.
+    fn fat_entry_width(&self) -> usize {
+        match self.fat_type {
+            FatType::Fat12 | FatType::Fat16 => 2,
+            FatType::Fat32 => 4,
+        }
+    }
+
+    /// A method to return the `FatEntry` for a cluster from the active FAT.
+    /// FAT12 and FAT16 entries are widened to the equivalent FAT32 entry.
+    pub(crate) fn fat_entry(&mut self, cluster: Cluster) -> io::Result<FatEntry> {
+        let offset = self.fat_type.entry_offset(cluster);
+        let width = self.fat_entry_width();
+        let mut bytes = [0u8; 4];
+        self.read_fat_bytes(self.active_fat(), offset, &mut bytes[..width])?;
+
+        let raw = from_le(&bytes);
+        let raw = match self.fat_type {
+            FatType::Fat12 if cluster.0 & 1 == 1 => raw >> 4,
+            FatType::Fat12 => raw & 0xFFF,
+            _ => raw
+        };
+        Ok(FatEntry(self.fat_type.widen(raw)))
+    }
+
+    /// Sets the FAT entry for `cluster` to `status` in every copy of the FAT,
+    /// or only in the active FAT if mirroring is disabled. The reserved upper
+    /// 4 bits of FAT32 entries and the neighbouring half bytes of FAT12
+    /// entries are preserved.
+    pub(crate) fn set_fat_entry(&mut self, cluster: Cluster, status: Status) -> io::Result<()> {
+        let offset = self.fat_type.entry_offset(cluster);
+        let width = self.fat_entry_width();
+        let value = status.to_raw();
+        let fats = match self.active_fat {
+            Some(fat) => fat..fat + 1,
+            None => 0..self.num_fats
+        };
+        for fat in fats {
+            let mut bytes = [0u8; 4];
+            self.read_fat_bytes(fat, offset, &mut bytes[..width])?;
+            let old = from_le(&bytes);
+            let new = match self.fat_type {
+                FatType::Fat12 if cluster.0 & 1 == 1 => (old & 0x000F) | (value & 0xFFF) << 4,
+                FatType::Fat12 => (old & 0xF000) | (value & 0xFFF),
+                FatType::Fat16 => value & 0xFFFF,
+                FatType::Fat32 => (old & 0xF0000000) | value,
+            };
+            self.write_fat_bytes(fat, offset, &to_le(new)[..width])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the cluster following `cluster` in its chain, or `None` if
+    /// `cluster` is the last cluster in the chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if `cluster` is free, reserved or bad
+    /// or if the next cluster is out of range.
+    pub(crate) fn next_cluster(&mut self, cluster: Cluster) -> io::Result<Option<Cluster>> {
+        match self.fat_entry(cluster)?.status() {
+            Status::Data(next) => {
+                self.check_cluster(next)?;
+                Ok(Some(next))
+            }
+            Status::Eoc(_) => Ok(None),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData,
+                                    "Fat entry is Free/Reserved/Bad"))
+        }
+    }
+
+    /// Allocates a free cluster, marks it as the end of its chain and, if
+    /// `prev` is `Some`, links it to the end of the chain ending at `prev`.
+    /// The contents of the new cluster are unspecified.
+    ///
+    /// The search starts at the next free cluster hint and wraps around to the
+    /// start of the FAT. The FAT is always searched, since the free cluster
+    /// count from the FSInfo structure is only a hint.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `Other` if there are no free clusters.
+    pub(crate) fn alloc_cluster(&mut self, prev: Option<Cluster>) -> io::Result<Cluster> {
+        let first = self.next_free;
+        let end = self.num_clusters + 2;
+        for raw in (first..end).chain(2..first) {
+            let cluster = Cluster(raw);
+            if self.fat_entry(cluster)?.status() == Status::Free {
+                self.set_fat_entry(cluster, EOC)?;
+                if let Some(prev) = prev {
+                    self.set_fat_entry(prev, Status::Data(cluster))?;
+                }
+                self.free_count = match self.free_count {
+                    // the count was stale
+                    Some(0) => Some(self.count_free_clusters()?),
+                    count => count.map(|count| count - 1)
+                };
+                self.next_free = if raw + 1 < end { raw + 1 } else { 2 };
+                self.fsinfo_dirty = true;
+                return Ok(cluster);
+            }
+        }
+
+        self.free_count = Some(0);
+        self.fsinfo_dirty = true;
+        Err(io::Error::new(io::ErrorKind::Other, "no free clusters"))
+    }
+
+    /// Allocates a run of `count` consecutive free clusters, links them into a
+    /// chain and, if `prev` is `Some`, links the run to the end of the chain
+    /// ending at `prev`. Returns the first cluster of the run. A run starting
+    /// right after `prev` is preferred so that the chain stays contiguous.
+    /// `count` must be at least 1.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `Other` if there is no run of `count` free
+    /// clusters.
+    pub(crate) fn alloc_contiguous(
+        &mut self,
+        prev: Option<Cluster>,
+        count: u32
+    ) -> io::Result<Cluster> {
+        let end = self.num_clusters + 2;
+        let first = match prev {
+            Some(prev) if prev.0 + 1 < end => prev.0 + 1,
+            _ => self.next_free
+        };
+
+        let mut start = None;
+        for &from in [first, 2].iter() {
+            let mut run = 0;
+            for raw in from..end {
+                if self.fat_entry(Cluster(raw))?.status() != Status::Free {
+                    run = 0;
+                } else if run + 1 == count {
+                    start = Some(raw + 1 - count);
+                    break;
+                } else {
+                    run += 1;
+                }
+            }
+            if start.is_some() {
+                break;
+            }
+        }
+        let start = start.ok_or(
+            io::Error::new(io::ErrorKind::Other, "no run of free clusters is long enough"))?;
+
+        for raw in start..start + count {
+            let status = match raw + 1 < start + count {
+                true => Status::Data(Cluster(raw + 1)),
+                false => EOC
+            };
+            self.set_fat_entry(Cluster(raw), status)?;
+        }
+        if let Some(prev) = prev {
+            self.set_fat_entry(prev, Status::Data(Cluster(start)))?;
+        }
+        self.free_count = match self.free_count {
+            // the count was stale
+            Some(free) if free < count => Some(self.count_free_clusters()?),
+            free => free.map(|free| free - count)
+        };
+        if self.next_free >= start && self.next_free < start + count {
+            self.next_free = if start + count < end { start + count } else { 2 };
+        }
+        self.fsinfo_dirty = true;
+        Ok(Cluster(start))
+    }
+
+    /// Frees every cluster in the chain starting at `start` in all copies of
+    /// the FAT. Does nothing if `start` is cluster 0, the first cluster of an
+    /// empty file.
+    pub(crate) fn free_chain(&mut self, start: Cluster) -> io::Result<()> {
+        if start.0 == 0 {
+            return Ok(());
+        }
+
+        // a directory created later may start at the same cluster
+        self.dentries.invalidate(start);
+        let mut cluster = start;
+        loop {
+            let next = self.next_cluster(cluster)?;
+            self.set_fat_entry(cluster, Status::Free)?;
+            self.free_count = self.free_count.map(|count| count + 1);
+            self.next_free = min(self.next_free, cluster.0);
+            self.fsinfo_dirty = true;
+            match next {
+                Some(next) => cluster = next,
+                None => return Ok(())
+            }
+        }
+    }
 
-        let fat_entries = 
-            self.device.get(self.fat_start_sector as u64 + fat_sector_index as u64)?;
-        let idx = (fat_entry_index * FAT_ENTRY_SIZE as u32) as usize;
-        let raw_fat_entry = from_le(&fat_entries[idx..idx + 4]);
-        Ok(FatEntry(raw_fat_entry))
+    /// Returns the last cluster of the chain starting at `start`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the chain is broken or has a cycle.
+    pub(crate) fn last_cluster(&mut self, start: Cluster) -> io::Result<Cluster> {
+        self.chain_end(start).map(|(last, _)| last)
+    }
+
+    /// Returns the last cluster of the chain starting at `start` and the
+    /// number of clusters in the chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the chain is broken or has a cycle.
+    pub(crate) fn chain_end(&mut self, start: Cluster) -> io::Result<(Cluster, u32)> {
+        let mut cluster = start;
+        for length in 1..self.num_clusters + 1 {
+            cluster = match self.next_cluster(cluster)? {
+                Some(next) => next,
+                None => return Ok((cluster, length))
+            };
+        }
+        Err(cycle())
+    }
+
+    /// Returns whether the directory starting at `ancestor` is the directory
+    /// starting at `dir` or one of its parents, found by following the `..`
+    /// entries up to the root directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of `InvalidData` if the `..` entries form a cycle.
+    fn is_ancestor(&mut self, ancestor: Cluster, dir: Cluster) -> io::Result<bool> {
+        let mut current = dir;
+        for _ in 0..self.num_clusters + 1 {
+            if current == ancestor {
+                return Ok(true);
+            } else if current == self.root_dir_cluster || current.0 == 0 {
+                return Ok(false);
+            }
+            let dotdot = VFatRegularDirEntry::from_bytes(self.read_dir_entry(current, 1)?);
+            current = dotdot.cluster();
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidData, "directory is its own parent"))
+    }
+
+    /// Marks all of the directory entries of the entry at `location` as
+    /// deleted.
+    pub(crate) fn delete_entries(&mut self, location: EntryLocation) -> io::Result<()> {
+        for index in location.start..location.regular + 1 {
+            let mut entry = self.read_dir_entry(location.dir, index)?;
+            entry[0] = ENTRY_DELETED;
+            self.write_dir_entry(location.dir, index, &entry)?;
+        }
+        Ok(())
+    }
+
+    /// Reads all of the directory entries of the directory starting at `dir`,
+    /// the fixed root directory region if `dir` refers to it, into `buf`.
+    /// Returns the number of bytes read.
+    pub(crate) fn read_dir(&mut self, dir: Cluster, buf: &mut Vec<u8>) -> io::Result<usize> {
+        let cluster = match self.dir_cluster(dir) {
+            Some(cluster) => return self.read_chain(cluster, buf),
+            None => dir
+        };
+
+        let start = buf.len();
+        let size = self.root_dir_entries as usize * BYTES_IN_ENTRY;
+        buf.resize(start + size, 0);
+        let mut pos = 0;
+        while pos < size {
+            let (sector, offset) = self.dir_entry_position(cluster, pos / BYTES_IN_ENTRY)?;
+            let data = self.device.get(sector)?;
+            let amount = min(data.len() - offset, size - pos);
+            buf[start + pos..start + pos + amount].copy_from_slice(&data[offset..offset + amount]);
+            pos += amount;
+        }
+        Ok(size)
+    }
+
+    /// Returns the logical sector and byte offset within that sector of the
+    /// `index`th 32-byte directory entry in the directory starting at `dir`.
+    fn dir_entry_position(&mut self, dir: Cluster, index: usize) -> io::Result<(u64, usize)> {
+        let out_of_range = || io::Error::new(io::ErrorKind::InvalidInput,
+                                             "directory entry index out of range");
+        let sector_size = self.bytes_per_sector as usize;
+        let offset = index * BYTES_IN_ENTRY;
+
+        let mut cluster = match self.dir_cluster(dir) {
+            Some(cluster) => cluster,
+            None if index < self.root_dir_entries as usize => {
+                return Ok((self.root_dir_start_sector + (offset / sector_size) as u64,
+                           offset % sector_size));
+            }
+            None => return Err(out_of_range())
+        };
+
+        for _ in 0..offset / self.cluster_size() {
+            cluster = self.next_cluster(cluster)?.ok_or_else(out_of_range)?;
+        }
+        let offset = offset % self.cluster_size();
+        Ok((self.cluster_sector(cluster) + (offset / sector_size) as u64, offset % sector_size))
+    }
+
+    /// Reads the raw `index`th directory entry in the directory starting at
+    /// `dir`.
+    pub(crate) fn read_dir_entry(
+        &mut self,
+        dir: Cluster,
+        index: usize
+    ) -> io::Result<[u8; BYTES_IN_ENTRY]> {
+        let (sector, offset) = self.dir_entry_position(dir, index)?;
+        let mut entry = [0; BYTES_IN_ENTRY];
+        entry.copy_from_slice(&self.device.get(sector)?[offset..offset + BYTES_IN_ENTRY]);
+        Ok(entry)
+    }
+
+    /// Overwrites the raw `index`th directory entry in the directory starting
+    /// at `dir` with `entry`, invalidating the cached entries in `dir`.
+    pub(crate) fn write_dir_entry(
+        &mut self,
+        dir: Cluster,
+        index: usize,
+        entry: &[u8; BYTES_IN_ENTRY]
+    ) -> io::Result<()> {
+        let cached_dir = self.dentry_dir(dir);
+        self.dentries.invalidate(cached_dir);
+        let (sector, offset) = self.dir_entry_position(dir, index)?;
+        self.device.get_mut(sector)?[offset..offset + BYTES_IN_ENTRY].copy_from_slice(entry);
+        Ok(())
+    }
+}
+
+/// The error returned when a cluster chain is longer than the volume, which
+/// means that it loops back on itself.
+fn cycle() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "cluster chain has a cycle")
+}
+
+/// Opens the directory containing the entry at the absolute path `path`.
+/// Returns the directory and the name of the entry.
+///
+/// # Errors
+///
+/// Returns an error of `InvalidInput` if `path` is not absolute, has no final
+/// component, or if its parent is not an existing directory.
+fn open_parent<'p>(vfat: &Shared<VFat>, path: &'p Path) -> io::Result<(Dir, &'p OsStr)> {
+    if !path.is_absolute() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is not absolute"));
+    }
+
+    let (parent, name) = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => (parent, name),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "path has no name"))
+    };
+
+    match vfat.open_dir(parent) {
+        Ok(dir) => Ok((dir, name)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound || e.kind() == io::ErrorKind::Other => {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "parent is not a directory"))
+        }
+        Err(e) => Err(e)
     }
 }
 
@@ -152,6 +1127,7 @@ impl<'a> FileSystem for &'a Shared<VFat> {
             start_cluster: self.borrow().root_dir_cluster,
             vfat: (*self).clone(),
             metadata: Default::default(),
+            location: None,
         }));
 
         for file_component in path.as_ref().components() {
@@ -172,33 +1148,129 @@ impl<'a> FileSystem for &'a Shared<VFat> {
                 Component::CurDir |
                 // root dir should only match first component, in which case we ignore
                 Component::RootDir => { },
-                Component::ParentDir => { traversed.pop(); },
-                Component::Prefix(_) => { unimplemented!() },
+                // the parent of the root directory is the root directory
+                Component::ParentDir => {
+                    if traversed.len() > 1 {
+                        traversed.pop();
+                    }
+                },
+                Component::Prefix(_) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              "path prefixes are not supported"));
+                }
             }
         }
         Ok(traversed.pop().unwrap())
     }
 
-    fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
-        unimplemented!("read only file system")
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        let (parent, name) = open_parent(self, path.as_ref())?;
+        parent.create_file(name)
     }
 
-    fn create_dir<P>(self, _path: P, _parents: bool) -> io::Result<Self::Dir>
+    fn create_dir<P>(self, path: P, parents: bool) -> io::Result<Self::Dir>
     where
         P: AsRef<Path>,
     {
-        unimplemented!("read only file system")
+        let path = path.as_ref();
+        if let (true, Some(parent)) = (parents, path.parent()) {
+            match self.open(parent) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                    self.create_dir(parent, true)?;
+                }
+                _ => {}
+            }
+        }
+
+        let (parent, name) = open_parent(self, path)?;
+        parent.create_dir(name)
     }
 
-    fn rename<P, Q>(self, _from: P, _to: Q) -> io::Result<()>
+    fn rename<P, Q>(self, from: P, to: Q) -> io::Result<()>
     where
         P: AsRef<Path>,
         Q: AsRef<Path>,
     {
-        unimplemented!("read only file system")
+        let (from, to) = (from.as_ref(), to.as_ref());
+        if !from.is_absolute() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is not absolute"));
+        }
+
+        let entry = self.open(from)?;
+        let location = entry.location().ok_or(
+            io::Error::new(io::ErrorKind::InvalidInput, "cannot rename the root directory"))?;
+        let (parent, name) = open_parent(self, to)?;
+        match parent.find(name) {
+            // only the case of the name is changing
+            Ok(ref existing) if existing.location() == Some(location) => {},
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, "entry exists")),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e)
+        }
+
+        if let Entry::Dir(ref dir) = entry {
+            if self.borrow_mut().is_ancestor(dir.start_cluster, parent.start_cluster)? {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          "cannot move a directory into itself"));
+            }
+        }
+
+        let name = name.to_str().ok_or(
+            io::Error::new(io::ErrorKind::InvalidInput, "name not valid utf8"))?;
+        let name = dir::validate_name(name)?;
+
+        // add the new entries before removing the old ones so that a failure
+        // part way through never loses the entry's data
+        let raw_entry = self.borrow_mut().read_dir_entry(location.dir, location.regular)?;
+        parent.insert(name, VFatRegularDirEntry::from_bytes(raw_entry))?;
+        self.borrow_mut().delete_entries(location)?;
+
+        match entry {
+            Entry::Dir(ref dir) if parent.start_cluster != location.dir => dir.set_parent(&parent),
+            _ => Ok(())
+        }
+    }
+
+    fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()> {
+        let path = path.as_ref();
+        if !path.is_absolute() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is not absolute"));
+        }
+
+        remove_entry(self, self.open(path)?, children)
+    }
+}
+
+/// Removes `entry` from its parent directory and frees its clusters. If
+/// `entry` is a directory and `children` is `true`, everything in it is
+/// recursively removed first.
+///
+/// # Errors
+///
+/// Returns an error of `Other` if `entry` is a non-empty directory and
+/// `children` is `false`. Returns an error of `InvalidInput` if `entry` is the
+/// root directory. Returns an error of `PermissionDenied` if `entry` is read
+/// only.
+fn remove_entry(vfat: &Shared<VFat>, entry: Entry, children: bool) -> io::Result<()> {
+    let location = entry.location().ok_or(
+        io::Error::new(io::ErrorKind::InvalidInput, "cannot remove the root directory"))?;
+    if traits::Entry::metadata(&entry).attributes.read_only() {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "entry is read only"));
     }
 
-    fn remove<P: AsRef<Path>>(self, _path: P, _children: bool) -> io::Result<()> {
-        unimplemented!("read only file system")
+    if let Entry::Dir(ref dir) = entry {
+        for child in traits::Dir::entries(dir)? {
+            let name = traits::Entry::name(&child);
+            if name == "." || name == ".." {
+                continue;
+            } else if !children {
+                return Err(io::Error::new(io::ErrorKind::Other, "directory not empty"));
+            }
+            remove_entry(vfat, child, true)?;
+        }
     }
+
+    let mut vfat = vfat.borrow_mut();
+    vfat.delete_entries(location)?;
+    vfat.free_chain(entry.start_cluster())
 }
@@ -28,9 +28,19 @@ impl FileSystem {
     pub fn initialize(&self) {
         *self.0.lock() = Some(VFat::from(Sd::new().unwrap()).unwrap());
     }
+
+    /// Returns the volume label of the file system, if it has one.
+    pub fn label(&self) -> io::Result<Option<String>> {
+        self.0.lock().as_ref().expect("fs uninitialized").borrow_mut().label()
+    }
+
+    /// Returns the volume serial number of the file system, if it has one.
+    pub fn volume_id(&self) -> Option<u32> {
+        self.0.lock().as_ref().expect("fs uninitialized").borrow().volume_id()
+    }
 }
 
-impl FileSystemTrait for FileSystem {
+impl<'a> FileSystemTrait for &'a FileSystem {
     type File = vfat::File;
     type Dir = vfat::Dir;
     type Entry = vfat::Entry;
@@ -39,26 +49,28 @@ impl FileSystemTrait for FileSystem {
         FileSystemTrait::open(&self.0.lock().as_ref().expect("fs uninitialized"), path)
     }
 
-    fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
-        unimplemented!("read only file system")
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        FileSystemTrait::create_file(self.0.lock().as_ref().expect("fs uninitialized"), path)
     }
 
-    fn create_dir<P>(self, _path: P, _parents: bool) -> io::Result<Self::Dir>
+    fn create_dir<P>(self, path: P, parents: bool) -> io::Result<Self::Dir>
     where
         P: AsRef<Path>,
     {
-        unimplemented!("read only file system")
+        FileSystemTrait::create_dir(
+            self.0.lock().as_ref().expect("fs uninitialized"), path, parents)
     }
 
-    fn rename<P, Q>(self, _from: P, _to: Q) -> io::Result<()>
+    fn rename<P, Q>(self, from: P, to: Q) -> io::Result<()>
     where
         P: AsRef<Path>,
         Q: AsRef<Path>,
     {
-        unimplemented!("read only file system")
+        FileSystemTrait::rename(self.0.lock().as_ref().expect("fs uninitialized"), from, to)
     }
 
-    fn remove<P: AsRef<Path>>(self, _path: P, _children: bool) -> io::Result<()> {
-        unimplemented!("read only file system")
+    fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()> {
+        FileSystemTrait::remove(
+            self.0.lock().as_ref().expect("fs uninitialized"), path, children)
     }
 }
@@ -4,7 +4,8 @@ use fat32::traits::{
     Dir as DirTrait,
     Entry as EntryTrait,
     Metadata as MetadataTrait,
-    Timestamp as TimestampTrait
+    Timestamp as TimestampTrait,
+    walk
 };
 use fat32::vfat::{Dir, File};
 use std::path::{Path, PathBuf};
@@ -51,7 +52,7 @@ fn print_io_error(kind: io::ErrorKind) {
 
 /// A wrapper around FILE_SYSTEM.open() that prints errors to the shell if any
 fn open_dir<P: AsRef<Path>>(path: P) -> Result<Dir, ()> {
-    match FILE_SYSTEM.open_dir(path) {
+    match (&FILE_SYSTEM).open_dir(path) {
         Err(err) => {
             print_io_error(err.kind());
             Err(())
@@ -61,7 +62,7 @@ fn open_dir<P: AsRef<Path>>(path: P) -> Result<Dir, ()> {
 }
 
 fn open_file<P: AsRef<Path>>(path: P) -> Result<File, ()> {
-    match FILE_SYSTEM.open_file(path) {
+    match (&FILE_SYSTEM).open_file(path) {
         Err(err) => {
             print_io_error(err.kind());
             Err(())
@@ -137,6 +138,37 @@ impl<'a> Command<'a> {
                     }
                 }
             },
+            "find" => {
+                let mut path = state.path.clone();
+                if self.args.len() > 1 {
+                    path.push(self.args[1]);
+                }
+                let pattern = if self.args.len() > 2 { self.args[2] } else { "" };
+
+                let entries = match walk(&&FILE_SYSTEM, path) {
+                    Ok(entries) => entries,
+                    Err(err) => return print_io_error(err.kind())
+                };
+                for item in entries {
+                    match item {
+                        Ok((path, ref entry)) if entry.name().contains(pattern) => {
+                            kprintln!("{}", path.display());
+                        },
+                        Ok(_) => { },
+                        Err(err) => print_io_error(err.kind())
+                    }
+                }
+            },
+            "mount" => {
+                kprint!("sd on / type vfat");
+                if let Ok(Some(label)) = FILE_SYSTEM.label() {
+                    kprint!(" [{}]", label);
+                }
+                match FILE_SYSTEM.volume_id() {
+                    Some(id) => kprintln!(" ({:04X}-{:04X})", id >> 16, id & 0xFFFF),
+                    None => kprintln!("")
+                }
+            },
             "pwd" => {
                 kprintln!("{:?}", state.path);
             },